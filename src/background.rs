@@ -0,0 +1,115 @@
+//!
+//! What a ray sees when it leaves the scene without hitting anything
+use hdr::{read_hdr, HdrError, HdrImage};
+use std::f64::consts::PI;
+use std::fmt::Debug;
+use std::path::Path;
+use vec3::{vec3, Ray, Vec3};
+
+pub trait Background: Sync + Send + Debug {
+    fn color(&self, r: &Ray) -> Vec3;
+}
+
+#[derive(Clone, Debug)]
+pub struct Constant {
+    pub color: Vec3,
+}
+
+impl Background for Constant {
+    fn color(&self, _r: &Ray) -> Vec3 {
+        self.color
+    }
+}
+
+/// Blends from `bottom` to `top` by the height of the ray direction.
+#[derive(Clone, Debug)]
+pub struct Gradient {
+    pub bottom: Vec3,
+    pub top: Vec3,
+}
+
+impl Default for Gradient {
+    fn default() -> Gradient {
+        Gradient {
+            bottom: vec3(1.0, 1.0, 1.0),
+            top: vec3(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Background for Gradient {
+    fn color(&self, r: &Ray) -> Vec3 {
+        let unit_direction = r.direction().unit_vector();
+        let t = 0.5 * (unit_direction.y() + 1.0);
+        (1.0 - t) * self.bottom + t * self.top
+    }
+}
+
+/// An equirectangular (latitude/longitude) environment map.
+///
+/// The image centre looks down -z, the top row is straight up (+y).
+#[derive(Clone, Debug)]
+pub struct EnvironmentMap {
+    pub image: HdrImage,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: HdrImage) -> EnvironmentMap {
+        EnvironmentMap { image }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<EnvironmentMap, HdrError> {
+        Ok(EnvironmentMap::new(read_hdr(path)?))
+    }
+
+    fn texel(&self, x: isize, y: isize) -> Vec3 {
+        let w = self.image.width as isize;
+        let h = self.image.height as isize;
+        let x = ((x % w) + w) % w;
+        let y = y.max(0).min(h - 1);
+        self.image.pixels[(y * w + x) as usize]
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn color(&self, r: &Ray) -> Vec3 {
+        let d = r.direction().unit_vector();
+        let u = 0.5 + d.x().atan2(-d.z()) / (2.0 * PI);
+        let v = d.y().clamp(-1.0, 1.0).acos() / PI;
+
+        // bilinear filtering between texel centres, wrapping around horizontally
+        let fx = u * self.image.width as f64 - 0.5;
+        let fy = v * self.image.height as f64 - 0.5;
+        let x0 = fx.floor();
+        let y0 = fy.floor();
+        let tx = fx - x0;
+        let ty = fy - y0;
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        let top = (1.0 - tx) * self.texel(x0, y0) + tx * self.texel(x0 + 1, y0);
+        let bottom = (1.0 - tx) * self.texel(x0, y0 + 1) + tx * self.texel(x0 + 1, y0 + 1);
+        (1.0 - ty) * top + ty * bottom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_map_orientation() {
+        // a 4x2 map: bright top row, dark bottom row
+        let mut pixels = vec![vec3(1, 1, 1); 4];
+        pixels.extend(vec![vec3(0, 0, 0); 4]);
+        let env = EnvironmentMap::new(HdrImage {
+            width: 4,
+            height: 2,
+            pixels,
+        });
+
+        let up = Ray::new(vec3(0, 0, 0), vec3(0, 1, 0), 0.0);
+        let down = Ray::new(vec3(0, 0, 0), vec3(0, -1, 0), 0.0);
+        assert_eq!(env.color(&up), vec3(1, 1, 1));
+        assert_eq!(env.color(&down), vec3(0, 0, 0));
+    }
+}
@@ -1,3 +1,6 @@
+use geo::{BvhBuilder, HitRecord, Hittable};
+use rand::prelude::*;
+use std::f64;
 use vec3::{vec3, Ray, Vec3};
 
 pub fn ffmin(a: f64, b: f64) -> f64 {
@@ -49,25 +52,508 @@ impl AABB {
         self.max
     }
 
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
     pub fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> bool {
+        self.hit_interval(r, tmin, tmax).is_some()
+    }
+
+    /// The part of `(tmin, tmax)` where the ray is inside the box.
+    pub fn hit_interval(&self, r: &Ray, mut tmin: f64, mut tmax: f64) -> Option<(f64, f64)> {
         for a in 0..3 {
             let t0 = ffmin(
-                self.min[a] - r.origin()[a] / r.direction()[a],
-                self.max[a] - r.origin()[a] / r.direction()[a],
+                (self.min[a] - r.origin()[a]) / r.direction()[a],
+                (self.max[a] - r.origin()[a]) / r.direction()[a],
             );
             let t1 = ffmax(
-                self.min[a] - r.origin()[a] / r.direction()[a],
-                self.max[a] - r.origin()[a] / r.direction()[a],
+                (self.min[a] - r.origin()[a]) / r.direction()[a],
+                (self.max[a] - r.origin()[a]) / r.direction()[a],
             );
 
-            let tmin = ffmax(t0, tmin);
-            let tmax = ffmin(t1, tmax);
+            tmin = ffmax(t0, tmin);
+            tmax = ffmin(t1, tmax);
 
             if tmax <= tmin {
-                return false;
+                return None;
             }
         }
 
+        Some((tmin, tmax))
+    }
+}
+
+/// Cost of visiting a node relative to intersecting one object.
+pub const SAH_TRAVERSAL_COST: f64 = 0.125;
+
+/// Where the binned surface area heuristic would split a set of boxes.
+pub struct SahSplit {
+    /// Expected cost of tracing the two sides, relative to intersecting one
+    /// object; a leaf holding every box costs their number.
+    pub cost: f64,
+    pub axis: usize,
+    /// Whether each box goes to the side with the smaller coordinates.
+    pub left: Vec<bool>,
+}
+
+/// Bins the boxes by their centers along the widest axis of the centers and
+/// tries a split between each pair of bins. Boxes whose centers can't be told
+/// apart are split in half by position in the slice, at an infinite cost.
+pub fn sah_split(boxes: &[AABB], bins: usize) -> SahSplit {
+    let bounds = boxes[1..]
+        .iter()
+        .fold(boxes[0], |acc, b| surrounding_box(&acc, b));
+    let first = AABB {
+        min: boxes[0].centroid(),
+        max: boxes[0].centroid(),
+    };
+    let centers = boxes[1..].iter().fold(first, |acc, b| {
+        let c = AABB {
+            min: b.centroid(),
+            max: b.centroid(),
+        };
+        surrounding_box(&acc, &c)
+    });
+
+    let extent = centers.max() - centers.min();
+    let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+        0
+    } else if extent.y() >= extent.z() {
+        1
+    } else {
+        2
+    };
+    if extent[axis] <= 0.0 {
+        return SahSplit {
+            cost: f64::INFINITY,
+            axis,
+            left: (0..boxes.len()).map(|i| i < boxes.len() / 2).collect(),
+        };
+    }
+
+    let bin_of = |b: &AABB| {
+        let offset = (b.centroid()[axis] - centers.min()[axis]) / extent[axis];
+        ((offset * bins as f64) as usize).min(bins - 1)
+    };
+    let mut counts = vec![0; bins];
+    let mut bin_boxes: Vec<Option<AABB>> = vec![None; bins];
+    for b in boxes {
+        let i = bin_of(b);
+        counts[i] += 1;
+        bin_boxes[i] = Some(match bin_boxes[i] {
+            Some(acc) => surrounding_box(&acc, b),
+            None => *b,
+        });
+    }
+
+    // sweep from the right so each candidate split knows the area and count
+    // of everything after it
+    let mut right_area = vec![0.0; bins];
+    let mut right_count = vec![0; bins];
+    let mut acc: Option<AABB> = None;
+    let mut count = 0;
+    for i in (1..bins).rev() {
+        if let Some(b) = bin_boxes[i] {
+            acc = Some(match acc {
+                Some(a) => surrounding_box(&a, &b),
+                None => b,
+            });
+        }
+        count += counts[i];
+        right_area[i] = acc.map_or(0.0, |a| a.surface_area());
+        right_count[i] = count;
+    }
+
+    let mut best = (f64::INFINITY, 0);
+    let mut acc: Option<AABB> = None;
+    let mut count = 0;
+    for i in 0..bins - 1 {
+        if let Some(b) = bin_boxes[i] {
+            acc = Some(match acc {
+                Some(a) => surrounding_box(&a, &b),
+                None => b,
+            });
+        }
+        count += counts[i];
+        if count == 0 || right_count[i + 1] == 0 {
+            continue;
+        }
+        let left_area = acc.map_or(0.0, |a| a.surface_area());
+        let cost = SAH_TRAVERSAL_COST
+            + (count as f64 * left_area + right_count[i + 1] as f64 * right_area[i + 1])
+                / bounds.surface_area();
+        if cost < best.0 {
+            best = (cost, i);
+        }
+    }
+
+    SahSplit {
+        cost: best.0,
+        axis,
+        left: boxes.iter().map(|b| bin_of(b) <= best.1).collect(),
+    }
+}
+
+/// Below this depth `LinearBvh` only splits nodes in half, which keeps the
+/// tree shallow enough for its fixed traversal stack.
+const LINEAR_BVH_SAH_DEPTH: usize = 32;
+const LINEAR_BVH_STACK: usize = 64;
+
+/// One node of a `LinearBvh`, 32 bytes so that two fit in a cache line.
+/// The bounds are rounded outwards to `f32`.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct LinearNode {
+    min: [f32; 3],
+    max: [f32; 3],
+    /// The first primitive of a leaf, or the second child of an interior
+    /// node, whose first child always follows it directly.
+    offset: u32,
+    /// Primitives in a leaf, 0 for an interior node.
+    count: u16,
+    /// The axis an interior node was split along. Its first child holds the
+    /// smaller coordinates.
+    axis: u8,
+    _pad: u8,
+}
+
+/// The next `f32` towards minus infinity, for anything but NaN and minus
+/// infinity. `f32::next_down` does the same but needs Rust 1.86.
+fn f32_below(f: f32) -> f32 {
+    if f == 0.0 {
+        -f32::from_bits(1)
+    } else if f > 0.0 {
+        f32::from_bits(f.to_bits() - 1)
+    } else {
+        f32::from_bits(f.to_bits() + 1)
+    }
+}
+
+impl LinearNode {
+    fn new(b: &AABB) -> LinearNode {
+        let down = |x: f64| {
+            let f = x as f32;
+            if f64::from(f) > x {
+                f32_below(f)
+            } else {
+                f
+            }
+        };
+        let up = |x: f64| {
+            let f = x as f32;
+            if f64::from(f) < x {
+                -f32_below(-f)
+            } else {
+                f
+            }
+        };
+        LinearNode {
+            min: [down(b.min.x()), down(b.min.y()), down(b.min.z())],
+            max: [up(b.max.x()), up(b.max.y()), up(b.max.z())],
+            offset: 0,
+            count: 0,
+            axis: 0,
+            _pad: 0,
+        }
+    }
+
+    /// Slab test with the inverse ray direction worked out once per ray.
+    fn hit(&self, origin: Vec3, inv_direction: Vec3, mut t_min: f64, mut t_max: f64) -> bool {
+        for a in 0..3 {
+            let t0 = (f64::from(self.min[a]) - origin[a]) * inv_direction[a];
+            let t1 = (f64::from(self.max[a]) - origin[a]) * inv_direction[a];
+            let (t0, t1) = if inv_direction[a] < 0.0 {
+                (t1, t0)
+            } else {
+                (t0, t1)
+            };
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max <= t_min {
+                return false;
+            }
+        }
         true
     }
 }
+
+/// A BVH flattened into an array of nodes in depth-first order, with each
+/// leaf covering a range of primitives stored in the same order.
+///
+/// Rays walk it with a small stack instead of recursion, visit the nearer
+/// child first and skip any node that starts beyond the closest hit so far.
+#[derive(Clone, Debug)]
+pub struct LinearBvh {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<Box<dyn Hittable>>,
+    bounds: Option<AABB>,
+}
+
+impl LinearBvh {
+    pub fn new(
+        objects: Vec<Box<dyn Hittable>>,
+        time0: f64,
+        time1: f64,
+        builder: BvhBuilder,
+    ) -> LinearBvh {
+        let boxes: Vec<AABB> = objects
+            .iter()
+            .map(|o| {
+                o.bounding_box(time0, time1)
+                    .expect("no bounding box for BVH object")
+            })
+            .collect();
+        let mut bvh = LinearBvh {
+            nodes: vec![],
+            primitives: vec![],
+            bounds: None,
+        };
+        if objects.is_empty() {
+            return bvh;
+        }
+
+        let mut order = Vec::with_capacity(objects.len());
+        let indices: Vec<usize> = (0..objects.len()).collect();
+        bvh.build(&boxes, indices, 0, builder, &mut order);
+
+        let mut slots: Vec<Option<Box<dyn Hittable>>> = objects.into_iter().map(Some).collect();
+        bvh.primitives = order.iter().map(|&i| slots[i].take().unwrap()).collect();
+        bvh.bounds = Some(
+            boxes[1..]
+                .iter()
+                .fold(boxes[0], |acc, b| surrounding_box(&acc, b)),
+        );
+        bvh
+    }
+
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    /// Appends the subtree over `indices` and returns its node index. Leaf
+    /// primitives are appended to `order` as they are placed.
+    fn build(
+        &mut self,
+        boxes: &[AABB],
+        mut indices: Vec<usize>,
+        depth: usize,
+        builder: BvhBuilder,
+        order: &mut Vec<usize>,
+    ) -> usize {
+        let own: Vec<AABB> = indices.iter().map(|&i| boxes[i]).collect();
+        let bounds = own[1..]
+            .iter()
+            .fold(own[0], |acc, b| surrounding_box(&acc, b));
+        let index = self.nodes.len();
+        self.nodes.push(LinearNode::new(&bounds));
+
+        let n = indices.len();
+        let max_leaf = match builder {
+            BvhBuilder::Median => 1,
+            BvhBuilder::Sah { max_leaf_size, .. } => max_leaf_size.min(u16::MAX as usize),
+        };
+        let (axis, left, right) = match builder {
+            _ if n == 1 => (0, indices, vec![]),
+            BvhBuilder::Sah { bins, .. } if depth < LINEAR_BVH_SAH_DEPTH => {
+                let split = sah_split(&own, bins);
+                if n <= max_leaf && split.cost >= n as f64 {
+                    (0, indices, vec![])
+                } else {
+                    let (mut left, mut right) = (vec![], vec![]);
+                    for (i, goes_left) in indices.into_iter().zip(split.left) {
+                        if goes_left {
+                            left.push(i);
+                        } else {
+                            right.push(i);
+                        }
+                    }
+                    (split.axis, left, right)
+                }
+            }
+            _ => {
+                let axis = if depth < LINEAR_BVH_SAH_DEPTH {
+                    thread_rng().gen_range(0, 3)
+                } else {
+                    0
+                };
+                indices
+                    .sort_by(|&a, &b| boxes[a].min[axis].partial_cmp(&boxes[b].min[axis]).unwrap());
+                let right = indices.split_off(n / 2);
+                (axis, indices, right)
+            }
+        };
+
+        if right.is_empty() {
+            self.nodes[index].offset = order.len() as u32;
+            self.nodes[index].count = left.len() as u16;
+            order.extend(left);
+        } else {
+            self.build(boxes, left, depth + 1, builder, order);
+            let second = self.build(boxes, right, depth + 1, builder, order);
+            self.nodes[index].offset = second as u32;
+            self.nodes[index].axis = axis as u8;
+        }
+        index
+    }
+}
+
+impl Hittable for LinearBvh {
+    fn hit(&self, r: &Ray, t_min: f64, mut t_max: f64) -> Option<HitRecord> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let origin = r.origin();
+        let inv_direction = r.direction().map(|c| 1.0 / c);
+
+        let mut stack = [0u32; LINEAR_BVH_STACK];
+        let mut pending = 0;
+        let mut current = 0;
+        let mut closest = None;
+        loop {
+            let node = &self.nodes[current];
+            if node.hit(origin, inv_direction, t_min, t_max) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for object in &self.primitives[first..first + node.count as usize] {
+                        if let Some(rec) = object.hit(r, t_min, t_max) {
+                            t_max = rec.t;
+                            closest = Some(rec);
+                        }
+                    }
+                } else {
+                    // push the far child and go on with the near one
+                    let (near, far) = if inv_direction[node.axis as usize] < 0.0 {
+                        (node.offset as usize, current + 1)
+                    } else {
+                        (current + 1, node.offset as usize)
+                    };
+                    stack[pending] = far as u32;
+                    pending += 1;
+                    current = near;
+                    continue;
+                }
+            }
+            if pending == 0 {
+                break;
+            }
+            pending -= 1;
+            current = stack[pending] as usize;
+        }
+        closest
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        self.bounds
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::tests::grey;
+    use geo::{HittableList, Sphere};
+    use std::mem;
+    use std::sync::Arc;
+
+    #[test]
+    fn aabb_hit_uses_slab_distances() {
+        let b = AABB {
+            min: vec3(2, -1, -1),
+            max: vec3(3, 1, 1),
+        };
+        let toward = Ray::new(vec3(0, 0, 0), vec3(1, 0, 0), 0.0);
+        let away = Ray::new(vec3(0, 0, 0), vec3(-1, 0, 0), 0.0);
+        let off_to_the_side = Ray::new(vec3(0, 5, 0), vec3(1, 0, 0), 0.0);
+
+        assert!(b.hit(&toward, 0.0, 10.0));
+        assert!(!b.hit(&toward, 0.0, 1.5));
+        assert!(!b.hit(&away, 0.0, 10.0));
+        assert!(!b.hit(&off_to_the_side, 0.0, 10.0));
+
+        // passes each slab, but never all three at once
+        let diagonal = Ray::new(vec3(0, 5, 0), vec3(1, -1, 0), 0.0);
+        assert!(!b.hit(&diagonal, 0.0, 10.0));
+        assert_eq!(b.hit_interval(&toward, 0.0, 10.0), Some((2.0, 3.0)));
+    }
+
+    fn spheres(count: usize) -> Vec<Box<dyn Hittable>> {
+        let material = grey();
+        let mut rng = thread_rng();
+        (0..count)
+            .map(|_| {
+                let center = vec3(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()) * 20.0;
+                Box::new(Sphere {
+                    center: center - 10.0,
+                    radius: 0.1 + rng.gen::<f64>(),
+                    material: Arc::clone(&material),
+                }) as Box<dyn Hittable>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn linear_bvh_matches_a_plain_list() {
+        assert_eq!(mem::size_of::<LinearNode>(), 32);
+
+        let objects = spheres(300);
+        let list = HittableList {
+            list: objects.clone(),
+        };
+        let small_leaves = BvhBuilder::Sah {
+            bins: 8,
+            max_leaf_size: 1,
+        };
+        let trees: Vec<LinearBvh> = [BvhBuilder::default(), BvhBuilder::Median, small_leaves]
+            .iter()
+            .map(|&builder| LinearBvh::new(objects.clone(), 0.0, 1.0, builder))
+            .collect();
+
+        let mut rng = thread_rng();
+        for _ in 0..500 {
+            let mut random = || vec3(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()) - 0.5;
+            let r = Ray::new(random() * 30.0, random(), 0.0);
+            let expected = list.hit(&r, 0.001, f64::MAX).map(|rec| rec.t);
+            for tree in &trees {
+                assert_eq!(tree.len(), 300);
+                assert_eq!(tree.hit(&r, 0.001, f64::MAX).map(|rec| rec.t), expected);
+            }
+        }
+
+        let empty = LinearBvh::new(vec![], 0.0, 1.0, BvhBuilder::default());
+        assert!(empty.is_empty() && empty.bounding_box(0.0, 1.0).is_none());
+        let r = Ray::new(vec3(0, 0, 0), vec3(1, 0, 0), 0.0);
+        assert!(empty.hit(&r, 0.001, f64::MAX).is_none());
+    }
+
+    #[test]
+    fn linear_node_bounds_round_outwards() {
+        let b = AABB {
+            min: vec3(0.1, -0.1, 1e-3),
+            max: vec3(0.3, 0.7, 1e10 + 1.0),
+        };
+        let node = LinearNode::new(&b);
+        for a in 0..3 {
+            assert!(f64::from(node.min[a]) <= b.min[a]);
+            assert!(f64::from(node.max[a]) >= b.max[a]);
+        }
+
+        assert_eq!(f32_below(1.0), 1.0 - f32::EPSILON / 2.0);
+        assert_eq!(f32_below(-1.0), -1.0 - f32::EPSILON);
+        assert!(f32_below(0.0) < 0.0 && f32_below(0.0) > -f32::MIN_POSITIVE);
+        assert_eq!(f32_below(f32::INFINITY), f32::MAX);
+    }
+}
@@ -1,6 +1,7 @@
 use rand::prelude::*;
 use std::f64::consts::PI;
 use vec3::{Ray, Vec3};
+#[derive(Clone, Debug)]
 pub struct Camera {
     pub origin: Vec3,
     pub lower_left_corner: Vec3,
@@ -13,6 +14,7 @@ pub struct Camera {
 
 impl Camera {
     // vfov is top to bottom in degrees
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         look_from: Vec3,
         look_at: Vec3,
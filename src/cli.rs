@@ -0,0 +1,319 @@
+//!
+//! Command line parsing for the renderer binary
+use std::str::FromStr;
+use weekend_raytracer::render::{Integrator, RenderSettings};
+use weekend_raytracer::scenefile::CameraDesc;
+use weekend_raytracer::tonemap::{ToneMap, ToneMapper};
+use weekend_raytracer::vec3::Vec3;
+
+pub const USAGE: &str = "Usage: weekend-raytracer [OPTIONS]
+
+Options:
+    -W, --width <PIXELS>       image width (default 800)
+    -H, --height <PIXELS>      image height (default 400)
+    -s, --spp <N>              samples per pixel (default 10)
+    -d, --max-depth <N>        maximum bounces per path (default 50)
+        --rr-depth <N>         bounces before Russian roulette may end a path
+                               (default 5)
+    -i, --integrator <NAME>    path, light to also sample lights directly, or
+                               mis to combine both strategies (default path)
+        --scene <NAME|FILE>    random_scene, simple_spheres, sphere_tree,
+                               lit_spheres, cornell_box or a scene file
+                               (default sphere_tree)
+        --export-scene <FILE>  write the scene description to FILE and exit
+        --look-from <X,Y,Z>    camera position (default from the scene)
+        --look-at <X,Y,Z>      camera target (default from the scene)
+        --vfov <DEGREES>       vertical field of view (default from the scene)
+        --aperture <A>         lens aperture (default from the scene)
+        --focus-dist <D>       focus distance (default from the scene)
+    -o, --output <FILE>        output file, .png, .hdr or .exr (default test1.png)
+        --tonemap <NAME>       clamp, reinhard, reinhard-extended or aces, for
+                               .png output (default clamp)
+        --white <L>            white point for reinhard-extended (default 4)
+        --exposure <STOPS>     exposure adjustment for .png output (default 0)
+    -j, --threads <N>          worker threads (default: one per core)
+    -h, --help                 print this message";
+
+/// Larger images are rejected rather than risk a huge allocation.
+const MAX_PIXELS: u64 = 1 << 28;
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub settings: RenderSettings,
+    pub scene: String,
+    pub export_scene: Option<String>,
+    pub look_from: Option<Vec3>,
+    pub look_at: Option<Vec3>,
+    pub vfov: Option<f64>,
+    pub aperture: Option<f64>,
+    pub focus_dist: Option<f64>,
+    pub output: String,
+    pub tone: ToneMapper,
+    pub threads: Option<usize>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            settings: RenderSettings::default(),
+            scene: String::from("sphere_tree"),
+            export_scene: None,
+            look_from: None,
+            look_at: None,
+            vfov: None,
+            aperture: None,
+            focus_dist: None,
+            output: String::from("test1.png"),
+            tone: ToneMapper::default(),
+            threads: None,
+        }
+    }
+}
+
+impl Options {
+    /// Applies the camera flags on top of the scene's own camera.
+    pub fn apply_camera(&self, camera: &mut CameraDesc) -> Result<(), String> {
+        if let Some(look_from) = self.look_from {
+            camera.look_from = look_from;
+        }
+        if let Some(look_at) = self.look_at {
+            camera.look_at = look_at;
+        }
+        if let Some(vfov) = self.vfov {
+            camera.vfov = vfov;
+        }
+        if let Some(aperture) = self.aperture {
+            camera.aperture = aperture;
+        }
+        if let Some(focus_dist) = self.focus_dist {
+            camera.focus_dist = focus_dist;
+        }
+        // checked after merging, since either end may come from the scene
+        if camera.look_from == camera.look_at {
+            return Err(String::from(
+                "the camera's look_from and look_at must differ",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Help,
+    Render(Box<Options>),
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<u32, String> {
+    match parse_number(flag, value)? {
+        0 => Err(format!("{} must be greater than zero", flag)),
+        n => Ok(n),
+    }
+}
+
+fn parse_vec3(flag: &str, value: &str) -> Result<Vec3, String> {
+    let parts = value
+        .split(',')
+        .map(|p| parse_number::<f64>(flag, p.trim()))
+        .collect::<Result<Vec<f64>, String>>()?;
+    if parts.len() != 3 {
+        return Err(format!("{} expects three comma separated numbers", flag));
+    }
+    Ok(Vec3::new([parts[0], parts[1], parts[2]]))
+}
+
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut opts = Options::default();
+    let mut args = args;
+    let mut tonemap = opts.tone.operator;
+    let mut white = None;
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+
+        // accept both `--flag value` and `--flag=value`
+        let (flag, inline) = match arg.find('=') {
+            Some(idx) if arg.starts_with("--") => {
+                (arg[..idx].to_string(), Some(arg[idx + 1..].to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+
+        let value = match inline {
+            Some(v) => v,
+            None => args
+                .next()
+                .ok_or_else(|| format!("missing value for {}", flag))?,
+        };
+
+        match flag.as_str() {
+            "-W" | "--width" => opts.settings.width = parse_positive(&flag, &value)?,
+            "-H" | "--height" => opts.settings.height = parse_positive(&flag, &value)?,
+            "-s" | "--spp" => opts.settings.samples = parse_positive(&flag, &value)?,
+            "-d" | "--max-depth" => opts.settings.max_depth = parse_positive(&flag, &value)?,
+            "--rr-depth" => opts.settings.roulette_depth = parse_number(&flag, &value)?,
+            "-i" | "--integrator" => {
+                opts.settings.integrator = match value.as_str() {
+                    "path" => Integrator::Path,
+                    "light" => Integrator::LightSampling,
+                    "mis" => Integrator::Mis,
+                    _ => return Err(format!("unknown integrator '{}'", value)),
+                }
+            }
+            "--scene" => opts.scene = value,
+            "--export-scene" => opts.export_scene = Some(value),
+            "--look-from" => opts.look_from = Some(parse_vec3(&flag, &value)?),
+            "--look-at" => opts.look_at = Some(parse_vec3(&flag, &value)?),
+            "--vfov" => {
+                let vfov: f64 = parse_number(&flag, &value)?;
+                if vfov <= 0.0 || vfov >= 180.0 {
+                    return Err(format!("{} must be between 0 and 180 degrees", flag));
+                }
+                opts.vfov = Some(vfov);
+            }
+            "--aperture" => {
+                let aperture: f64 = parse_number(&flag, &value)?;
+                if aperture < 0.0 {
+                    return Err(format!("{} must not be negative", flag));
+                }
+                opts.aperture = Some(aperture);
+            }
+            "--focus-dist" => {
+                let focus_dist: f64 = parse_number(&flag, &value)?;
+                if focus_dist <= 0.0 {
+                    return Err(format!("{} must be greater than zero", flag));
+                }
+                opts.focus_dist = Some(focus_dist);
+            }
+            "-o" | "--output" => opts.output = value,
+            "--tonemap" => {
+                tonemap = match value.as_str() {
+                    "clamp" => ToneMap::Clamp,
+                    "reinhard" => ToneMap::Reinhard,
+                    "reinhard-extended" => ToneMap::ExtendedReinhard { white: 4.0 },
+                    "aces" => ToneMap::Aces,
+                    _ => return Err(format!("unknown tone map '{}'", value)),
+                }
+            }
+            "--white" => {
+                let w: f64 = parse_number(&flag, &value)?;
+                if w <= 0.0 {
+                    return Err(format!("{} must be greater than zero", flag));
+                }
+                white = Some(w);
+            }
+            "--exposure" => opts.tone.exposure = parse_number(&flag, &value)?,
+            "-j" | "--threads" => opts.threads = Some(parse_positive(&flag, &value)? as usize),
+            _ => return Err(format!("unknown option '{}'", flag)),
+        }
+    }
+
+    opts.tone.operator = match (tonemap, white) {
+        (ToneMap::ExtendedReinhard { .. }, Some(white)) => ToneMap::ExtendedReinhard { white },
+        (_, Some(_)) => return Err(String::from("--white needs --tonemap reinhard-extended")),
+        (operator, None) => operator,
+    };
+
+    let (width, height) = (opts.settings.width, opts.settings.height);
+    if u64::from(width) * u64::from(height) > MAX_PIXELS {
+        return Err(format!("a {}x{} image is too large", width, height));
+    }
+
+    Ok(Command::Render(Box::new(opts)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use weekend_raytracer::vec3::vec3;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    fn parse_opts(s: &str) -> Result<Options, String> {
+        match parse(args(s).into_iter())? {
+            Command::Render(opts) => Ok(*opts),
+            Command::Help => Err(String::from("help")),
+        }
+    }
+
+    #[test]
+    fn defaults_without_arguments() {
+        let opts = parse_opts("").unwrap();
+        assert_eq!(opts.settings.width, 800);
+        assert_eq!(opts.scene, "sphere_tree");
+        assert_eq!(opts.output, "test1.png");
+    }
+
+    #[test]
+    fn parses_flags_and_inline_values() {
+        let opts =
+            parse_opts("-W 320 --height=240 --spp 4 --look-from 1,2,3 -o out.png -j 2").unwrap();
+        assert_eq!(opts.settings.width, 320);
+        assert_eq!(opts.settings.height, 240);
+        assert_eq!(opts.settings.samples, 4);
+        assert_eq!(opts.look_from, Some(vec3(1, 2, 3)));
+        assert_eq!(opts.output, "out.png");
+        assert_eq!(opts.threads, Some(2));
+    }
+
+    #[test]
+    fn parses_tone_mapping() {
+        let opts = parse_opts("--white 8 --tonemap reinhard-extended --exposure -1").unwrap();
+        assert_eq!(opts.tone.operator, ToneMap::ExtendedReinhard { white: 8.0 });
+        assert_eq!(opts.tone.exposure, -1.0);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(parse_opts("--width 0").is_err());
+        assert!(parse_opts("--spp ten").is_err());
+        assert!(parse_opts("--look-at 1,2").is_err());
+        assert!(parse_opts("--bogus 1").is_err());
+        assert!(parse_opts("--width").is_err());
+        assert!(parse_opts("--integrator magic").is_err());
+        assert!(parse_opts("--white 2").is_err());
+        assert!(parse_opts("-W 100000 -H 100000").is_err());
+        assert!(parse_opts("-W 4294967295 -H 2").is_err());
+    }
+
+    #[test]
+    fn camera_flags_are_checked_against_the_scene() {
+        let scene = CameraDesc::default();
+        let mut camera = scene.clone();
+        let opts = parse_opts("--look-from 1,2,3 --vfov 30").unwrap();
+        opts.apply_camera(&mut camera).unwrap();
+        assert_eq!(camera.look_from, vec3(1, 2, 3));
+        assert_eq!(camera.look_at, scene.look_at);
+        assert_eq!(camera.vfov, 30.0);
+
+        let mut camera = scene.clone();
+        let at_target = format!(
+            "--look-from {},{},{}",
+            scene.look_at.x(),
+            scene.look_at.y(),
+            scene.look_at.z()
+        );
+        assert!(parse_opts(&at_target)
+            .unwrap()
+            .apply_camera(&mut camera)
+            .is_err());
+    }
+
+    #[test]
+    fn help_short_circuits() {
+        match parse(args("--help --width 0").into_iter()).unwrap() {
+            Command::Help => (),
+            _ => panic!("expected help"),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//!
+//! Constructive solid geometry on closed shapes.
+//!
+//! Operands must be closed and report outward normals, like `Sphere`,
+//! `BoxShape` or capped cylinders. The result is found by walking the surface
+//! crossings of both operands along the ray and keeping the ones where the
+//! inside/outside state of the combination changes.
+use bvh::{surrounding_box, AABB};
+use geo::{HitRecord, Hittable};
+use vec3::{dot, Ray};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// The left shape with the right one carved out of it.
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// A stretch of the ray that lies inside a shape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interval {
+    pub enter: f64,
+    pub exit: f64,
+}
+
+fn is_entry(r: &Ray, rec: &HitRecord) -> bool {
+    dot(rec.normal, r.direction()) < 0.0
+}
+
+/// A ray whose first crossing leaves a shape started inside it.
+fn starts_inside(r: &Ray, crossings: &[HitRecord]) -> bool {
+    match crossings.first() {
+        Some(rec) => !is_entry(r, rec),
+        None => false,
+    }
+}
+
+/// The parts of `(t_min, t_max)` that lie inside `shape`. An interval starts
+/// at `t_min` if the ray begins inside, and ends at `t_max` if it never
+/// leaves.
+pub fn intervals(shape: &dyn Hittable, r: &Ray, t_min: f64, t_max: f64) -> Vec<Interval> {
+    let mut result = vec![];
+    let mut enter = None;
+    // whether the ray starts inside is only known from the first crossing,
+    // which may lie beyond t_max, so look along the whole ray and clip after
+    for (i, rec) in shape.crossings(r, t_min, f64::MAX).iter().enumerate() {
+        if is_entry(r, rec) {
+            enter = Some(rec.t);
+        } else if enter.is_some() || i == 0 {
+            result.push(Interval {
+                enter: enter.unwrap_or(t_min),
+                exit: rec.t,
+            });
+            enter = None;
+        }
+    }
+    if let Some(enter) = enter {
+        result.push(Interval {
+            enter,
+            exit: f64::MAX,
+        });
+    }
+    result
+        .into_iter()
+        .filter(|i| i.enter < t_max)
+        .map(|i| Interval {
+            exit: i.exit.min(t_max),
+            ..i
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct Csg {
+    pub op: CsgOp,
+    pub left: Box<dyn Hittable>,
+    pub right: Box<dyn Hittable>,
+}
+
+impl Csg {
+    pub fn new(op: CsgOp, left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg { op, left, right }
+    }
+
+    pub fn union(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg::new(CsgOp::Union, left, right)
+    }
+
+    pub fn intersection(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg::new(CsgOp::Intersection, left, right)
+    }
+
+    pub fn difference(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg::new(CsgOp::Difference, left, right)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.crossings(r, t_min, t_max).into_iter().next()
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let left = self.left.bounding_box(t0, t1);
+        let right = self.right.bounding_box(t0, t1);
+        match self.op {
+            CsgOp::Union => Some(surrounding_box(&left?, &right?)),
+            CsgOp::Intersection => match (left, right) {
+                (Some(l), Some(r)) => Some(AABB {
+                    min: l.min().apply_per_element(r.min(), f64::max),
+                    max: l.max().apply_per_element(r.max(), f64::min),
+                }),
+                (l, r) => l.or(r),
+            },
+            CsgOp::Difference => left,
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+
+    fn crossings(&self, r: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord> {
+        // the inside state at t_min comes from each operand's first crossing,
+        // so they are found along the whole ray and clipped to t_max below
+        let left = self.left.crossings(r, t_min, f64::MAX);
+        let right = self.right.crossings(r, t_min, f64::MAX);
+
+        let mut in_left = starts_inside(r, &left);
+        let mut in_right = starts_inside(r, &right);
+        let mut inside = self.op.inside(in_left, in_right);
+
+        let mut result = vec![];
+        let (mut li, mut ri) = (0, 0);
+        while li < left.len() || ri < right.len() {
+            let from_left = ri == right.len() || (li < left.len() && left[li].t <= right[ri].t);
+            let rec = if from_left {
+                li += 1;
+                in_left = is_entry(r, &left[li - 1]);
+                &left[li - 1]
+            } else {
+                ri += 1;
+                in_right = is_entry(r, &right[ri - 1]);
+                &right[ri - 1]
+            };
+            if rec.t >= t_max {
+                break;
+            }
+
+            let now_inside = self.op.inside(in_left, in_right);
+            if now_inside != inside {
+                inside = now_inside;
+                // keep the normal pointing out of the result, which for the
+                // carved-out shape means flipping it
+                let normal = if is_entry(r, rec) == now_inside {
+                    rec.normal
+                } else {
+                    -rec.normal
+                };
+                result.push(HitRecord {
+                    normal,
+                    ..rec.clone()
+                });
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::tests::{ball, grey};
+    use geo::BoxShape;
+    use std::f64;
+    use vec3::vec3;
+
+    fn along_x() -> Ray {
+        Ray::new(vec3(-10, 0, 0), vec3(1, 0, 0), 0.0)
+    }
+
+    fn spans(shape: &dyn Hittable) -> Vec<(f64, f64)> {
+        intervals(shape, &along_x(), 0.001, f64::MAX)
+            .iter()
+            .map(|i| ((i.enter * 1e6).round() / 1e6, (i.exit * 1e6).round() / 1e6))
+            .collect()
+    }
+
+    #[test]
+    fn intervals_of_a_single_shape() {
+        assert_eq!(spans(&*ball(vec3(0.0, 0.0, 0.0), 1.0)), vec![(9.0, 11.0)]);
+        let from_inside = intervals(
+            &*ball(vec3(0.0, 0.0, 0.0), 1.0),
+            &Ray::new(vec3(0, 0, 0), vec3(1, 0, 0), 0.0),
+            0.001,
+            100.0,
+        );
+        assert_eq!(
+            from_inside,
+            vec![Interval {
+                enter: 0.001,
+                exit: 1.0
+            }]
+        );
+    }
+
+    #[test]
+    fn results_do_not_depend_on_t_max() {
+        // from the centre the inner ball's exit at t=1 is still inside the
+        // outer one, so the only surface is at t=2
+        let union = Csg::union(ball(vec3(0, 0, 0), 2.0), ball(vec3(0, 0, 0), 1.0));
+        let r = Ray::new(vec3(0, 0, 0), vec3(1, 0, 0), 0.0);
+        assert_eq!(union.hit(&r, 0.001, 1e30).unwrap().t, 2.0);
+        assert!(union.hit(&r, 0.001, 1.5).is_none());
+        assert_eq!(
+            intervals(&union, &r, 0.001, 1.5),
+            vec![Interval {
+                enter: 0.001,
+                exit: 1.5
+            }]
+        );
+    }
+
+    #[test]
+    fn boolean_operations() {
+        let union = Csg::union(
+            ball(vec3(0.0, 0.0, 0.0), 1.0),
+            ball(vec3(1.5, 0.0, 0.0), 1.0),
+        );
+        assert_eq!(spans(&union), vec![(9.0, 12.5)]);
+
+        let intersection = Csg::intersection(
+            ball(vec3(0.0, 0.0, 0.0), 1.0),
+            ball(vec3(1.5, 0.0, 0.0), 1.0),
+        );
+        assert_eq!(spans(&intersection), vec![(10.5, 11.0)]);
+
+        let difference = Csg::difference(
+            ball(vec3(0.0, 0.0, 0.0), 1.0),
+            ball(vec3(1.5, 0.0, 0.0), 1.0),
+        );
+        assert_eq!(spans(&difference), vec![(9.0, 10.5)]);
+        let rec = difference.hit(&along_x(), 10.0, f64::MAX).unwrap();
+        // the carved face points back out of the remaining solid
+        assert!((rec.normal - vec3(1, 0, 0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn hollow_ball_and_nesting() {
+        let shell = Csg::difference(
+            ball(vec3(0.0, 0.0, 0.0), 1.0),
+            ball(vec3(0.0, 0.0, 0.0), 0.9),
+        );
+        assert_eq!(spans(&shell), vec![(9.0, 9.1), (10.9, 11.0)]);
+
+        // a box with a hole, intersected with a ball
+        let holed = Csg::difference(
+            Box::new(BoxShape::new(vec3(-1, -1, -1), vec3(1, 1, 1), grey())),
+            ball(vec3(0.0, 0.0, 0.0), 0.5),
+        );
+        let nested = Csg::intersection(Box::new(holed), ball(vec3(0.0, 0.0, 0.0), 0.8));
+        assert_eq!(spans(&nested), vec![(9.2, 9.5), (10.5, 10.8)]);
+        let b = nested.bounding_box(0.0, 0.0).unwrap();
+        assert!((b.max() - vec3(0.8, 0.8, 0.8)).length() < 1e-9);
+    }
+}
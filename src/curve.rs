@@ -0,0 +1,383 @@
+//!
+//! Cubic Bézier curves with varying width, for hair, fur and grass.
+//!
+//! Intersection follows the subdivision approach from pbrt: the curve is
+//! moved into a frame looking down the ray, split until each piece is close
+//! enough to a line, and each piece is tested as a line segment whose width
+//! is interpolated along it.
+use bvh::AABB;
+use geo::{HitRecord, Hittable};
+use material::Material;
+use std::sync::Arc;
+use vec3::{dot, vec3, Onb, Ray, Vec3};
+
+/// How the cross section of a curve is shaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveKind {
+    /// A flat strip that always faces the ray, cheapest for fine hair.
+    Ribbon,
+    /// A round tube, for strands thick enough for their shading to show.
+    Cylinder,
+}
+
+/// The point at `u` on a cubic Bézier curve.
+pub fn bezier(cp: &[Vec3; 4], u: f64) -> Vec3 {
+    blossom(cp, u, u, u)
+}
+
+/// The derivative at `u` of a cubic Bézier curve.
+pub fn bezier_derivative(cp: &[Vec3; 4], u: f64) -> Vec3 {
+    let a = cp[1] - cp[0];
+    let b = cp[2] - cp[1];
+    let c = cp[3] - cp[2];
+    let s = 1.0 - u;
+    3.0 * (s * s * a + 2.0 * s * u * b + u * u * c)
+}
+
+/// De Casteljau's algorithm with a different parameter at each level. The
+/// control points of the piece between `u0` and `u1` are the blossoms
+/// `(u0, u0, u0)`, `(u0, u0, u1)`, `(u0, u1, u1)` and `(u1, u1, u1)`.
+fn blossom(cp: &[Vec3; 4], u0: f64, u1: f64, u2: f64) -> Vec3 {
+    let lerp = |t: f64, a: Vec3, b: Vec3| (1.0 - t) * a + t * b;
+    let a = [
+        lerp(u0, cp[0], cp[1]),
+        lerp(u0, cp[1], cp[2]),
+        lerp(u0, cp[2], cp[3]),
+    ];
+    let b = [lerp(u1, a[0], a[1]), lerp(u1, a[1], a[2])];
+    lerp(u2, b[0], b[1])
+}
+
+/// Control points of the part of a curve between `u0` and `u1`.
+pub fn sub_curve(cp: &[Vec3; 4], u0: f64, u1: f64) -> [Vec3; 4] {
+    [
+        blossom(cp, u0, u0, u0),
+        blossom(cp, u0, u0, u1),
+        blossom(cp, u0, u1, u1),
+        blossom(cp, u1, u1, u1),
+    ]
+}
+
+fn control_box(cp: &[Vec3; 4], pad: f64) -> AABB {
+    let mut min = cp[0];
+    let mut max = cp[0];
+    for p in &cp[1..] {
+        min = vec3(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z()));
+        max = vec3(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z()));
+    }
+    let pad = vec3(pad, pad, pad);
+    AABB {
+        min: min - pad,
+        max: max + pad,
+    }
+}
+
+/// A hit in the ray frame: distance along the ray, curve parameter and
+/// position across the width.
+struct CurveHit {
+    z: f64,
+    u: f64,
+    v: f64,
+}
+
+/// A cubic Bézier curve whose width changes linearly from `width.0` at the
+/// start to `width.1` at the end.
+#[derive(Clone, Debug)]
+pub struct Curve {
+    pub control: [Vec3; 4],
+    pub width: (f64, f64),
+    /// The `u` reported at either end, so that the pieces made by `segments`
+    /// still report the parameter along the whole curve.
+    pub u_range: (f64, f64),
+    pub kind: CurveKind,
+    pub material: Arc<dyn Material>,
+}
+
+impl Curve {
+    pub fn new(
+        control: [Vec3; 4],
+        width: (f64, f64),
+        kind: CurveKind,
+        material: Arc<dyn Material>,
+    ) -> Curve {
+        Curve {
+            control,
+            width,
+            u_range: (0.0, 1.0),
+            kind,
+            material,
+        }
+    }
+
+    /// Splits the curve into `n` pieces. A long curve's bounding box is mostly
+    /// empty space, so handing the pieces to `BVHNode` gives a tighter tree.
+    pub fn segments(&self, n: usize) -> Vec<Box<dyn Hittable>> {
+        (0..n)
+            .map(|i| {
+                let (u0, u1) = (i as f64 / n as f64, (i + 1) as f64 / n as f64);
+                let report = |u: f64| self.u_range.0 + u * (self.u_range.1 - self.u_range.0);
+                Box::new(Curve {
+                    control: sub_curve(&self.control, u0, u1),
+                    width: (self.width_at(u0), self.width_at(u1)),
+                    u_range: (report(u0), report(u1)),
+                    kind: self.kind,
+                    material: Arc::clone(&self.material),
+                }) as Box<dyn Hittable>
+            })
+            .collect()
+    }
+
+    fn width_at(&self, u: f64) -> f64 {
+        (1.0 - u) * self.width.0 + u * self.width.1
+    }
+
+    /// Subdivisions needed before the pieces are flat to within a twentieth
+    /// of the width.
+    fn max_depth(&self) -> u32 {
+        let cp = &self.control;
+        let l0 = (0..2)
+            .map(|i| (cp[i] - 2.0 * cp[i + 1] + cp[i + 2]).length())
+            .fold(0.0, f64::max);
+        let eps = self.width.0.max(self.width.1) * 0.05;
+        if l0 <= 0.0 || eps <= 0.0 {
+            return 0;
+        }
+        let depth = (2f64.sqrt() * 6.0 * l0 / (8.0 * eps)).log2() / 2.0;
+        depth.clamp(0.0, 10.0) as u32
+    }
+
+    /// `cp` is the whole curve and `piece` the part of it between `u0` and
+    /// `u1`, both in the ray frame where the ray starts at the origin and
+    /// runs along +z.
+    fn recurse(
+        &self,
+        cp: &[Vec3; 4],
+        piece: &[Vec3; 4],
+        (u0, u1): (f64, f64),
+        depth: u32,
+        z_min: f64,
+        z_max: f64,
+    ) -> Option<CurveHit> {
+        let half_width = self.width_at(u0).max(self.width_at(u1)) / 2.0;
+        let b = control_box(piece, half_width);
+        if b.min.x() > 0.0 || b.max.x() < 0.0 || b.min.y() > 0.0 || b.max.y() < 0.0 {
+            return None;
+        }
+        if b.max.z() < z_min || b.min.z() > z_max {
+            return None;
+        }
+
+        if depth > 0 {
+            let mid = (u0 + u1) / 2.0;
+            let first = sub_curve(piece, 0.0, 0.5);
+            let second = sub_curve(piece, 0.5, 1.0);
+            let near = self.recurse(cp, &first, (u0, mid), depth - 1, z_min, z_max);
+            let z_max = near.as_ref().map_or(z_max, |h| h.z);
+            let far = self.recurse(cp, &second, (mid, u1), depth - 1, z_min, z_max);
+            return far.or(near);
+        }
+
+        // the origin must lie between the planes through either end that are
+        // perpendicular to the curve there, or neighbouring pieces would both
+        // claim it
+        let (p0, p3) = (piece[0], piece[3]);
+        let start = piece[1] - p0;
+        let end = piece[2] - p3;
+        if -p0.x() * start.x() - p0.y() * start.y() < 0.0 {
+            return None;
+        }
+        if -p3.x() * end.x() - p3.y() * end.y() < 0.0 {
+            return None;
+        }
+
+        // closest point to the origin on the line through the piece's ends
+        let segment = vec3(p3.x() - p0.x(), p3.y() - p0.y(), 0.0);
+        let length2 = segment.squared_length();
+        let w = if length2 > 0.0 {
+            ((-p0.x() * segment.x() - p0.y() * segment.y()) / length2).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let u = u0 + w * (u1 - u0);
+
+        let pc = bezier(cp, u);
+        let distance2 = pc.x() * pc.x() + pc.y() * pc.y();
+        let half_width = self.width_at(u) / 2.0;
+        if distance2 > half_width * half_width {
+            return None;
+        }
+
+        let z = match self.kind {
+            CurveKind::Ribbon => pc.z(),
+            CurveKind::Cylinder => {
+                // the front of the tube, or its back for rays that start on it
+                let depth = (half_width * half_width - distance2).sqrt();
+                if pc.z() - depth > z_min {
+                    pc.z() - depth
+                } else {
+                    pc.z() + depth
+                }
+            }
+        };
+        if z <= z_min || z >= z_max {
+            return None;
+        }
+
+        let direction = bezier_derivative(cp, u);
+        let distance = distance2.sqrt();
+        let side = direction.x() * -pc.y() + pc.x() * direction.y();
+        let v = if side > 0.0 {
+            0.5 + distance / (2.0 * half_width)
+        } else {
+            0.5 - distance / (2.0 * half_width)
+        };
+        Some(CurveHit { z, u, v })
+    }
+}
+
+impl Hittable for Curve {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let speed = r.direction().length();
+        let frame = Onb::from_w(r.direction());
+        let to_ray = |p: Vec3| {
+            let d = p - r.origin();
+            vec3(dot(d, frame.u), dot(d, frame.v), dot(d, frame.w))
+        };
+        let cp = [
+            to_ray(self.control[0]),
+            to_ray(self.control[1]),
+            to_ray(self.control[2]),
+            to_ray(self.control[3]),
+        ];
+
+        let hit = self.recurse(
+            &cp,
+            &cp,
+            (0.0, 1.0),
+            self.max_depth(),
+            t_min * speed,
+            t_max * speed,
+        )?;
+
+        let t = hit.z / speed;
+        let p = r.point_at_parameter(t);
+        let tangent = bezier_derivative(&self.control, hit.u).unit_vector();
+        let facing = -r.direction().unit_vector();
+        let normal = match self.kind {
+            CurveKind::Ribbon => facing,
+            CurveKind::Cylinder => {
+                let out = p - bezier(&self.control, hit.u);
+                let out = out - dot(out, tangent) * tangent;
+                if out.squared_length() > 0.0 {
+                    out.unit_vector()
+                } else {
+                    facing
+                }
+            }
+        };
+        Some(HitRecord {
+            t,
+            p,
+            normal,
+            u: self.u_range.0 + hit.u * (self.u_range.1 - self.u_range.0),
+            v: hit.v,
+            tangent: Some(tangent),
+            color: None,
+            material: Arc::clone(&self.material),
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(control_box(
+            &self.control,
+            self.width.0.max(self.width.1) / 2.0,
+        ))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::tests::grey;
+    use std::f64;
+
+    fn curve(control: [Vec3; 4], width: (f64, f64), kind: CurveKind) -> Curve {
+        Curve::new(control, width, kind, grey())
+    }
+
+    fn straight() -> [Vec3; 4] {
+        [
+            vec3(-1, 0, 0),
+            vec3(-0.3, 0.0, 0.0),
+            vec3(0.3, 0.0, 0.0),
+            vec3(1, 0, 0),
+        ]
+    }
+
+    #[test]
+    fn straight_tube_and_ribbon() {
+        let down = Ray::new(vec3(0, 0, 5), vec3(0, 0, -1), 0.0);
+        let tube = curve(straight(), (0.2, 0.2), CurveKind::Cylinder);
+        let rec = tube.hit(&down, 0.001, f64::MAX).unwrap();
+        assert!((rec.p.z() - 0.1).abs() < 1e-6);
+        assert!((rec.normal - vec3(0, 0, 1)).length() < 1e-6);
+        assert!((rec.tangent.unwrap() - vec3(1, 0, 0)).length() < 1e-9);
+        assert!((rec.u - 0.5).abs() < 1e-6);
+
+        // leaving the tube from its surface finds the far side
+        let through = Ray::new(rec.p, vec3(0, 0, -1), 0.0);
+        let exit = tube.hit(&through, 0.001, f64::MAX).unwrap();
+        assert!((exit.p.z() + 0.1).abs() < 1e-6);
+        assert!(exit.normal.z() < 0.0);
+
+        let ribbon = curve(straight(), (0.2, 0.2), CurveKind::Ribbon);
+        let rec = ribbon.hit(&down, 0.001, f64::MAX).unwrap();
+        assert!(rec.p.z().abs() < 1e-6);
+
+        let beside = Ray::new(vec3(0.0, 0.15, 5.0), vec3(0, 0, -1), 0.0);
+        assert!(tube.hit(&beside, 0.001, f64::MAX).is_none());
+        let past_the_end = Ray::new(vec3(1.2, 0.0, 5.0), vec3(0, 0, -1), 0.0);
+        assert!(tube.hit(&past_the_end, 0.001, f64::MAX).is_none());
+    }
+
+    #[test]
+    fn width_tapers_along_the_curve() {
+        let strand = curve(straight(), (0.4, 0.0), CurveKind::Ribbon);
+        let thick = Ray::new(vec3(-0.5, 0.12, 5.0), vec3(0, 0, -1), 0.0);
+        let thin = Ray::new(vec3(0.5, 0.12, 5.0), vec3(0, 0, -1), 0.0);
+        let rec = strand.hit(&thick, 0.001, f64::MAX).unwrap();
+        assert!(rec.v > 0.5 && rec.v < 1.0);
+        assert!(strand.hit(&thin, 0.001, f64::MAX).is_none());
+    }
+
+    #[test]
+    fn bent_curve_and_segments() {
+        let control = [vec3(0, 0, 0), vec3(1, 2, 0), vec3(2, -2, 0), vec3(3, 0, 0)];
+        let strand = curve(control, (0.05, 0.05), CurveKind::Cylinder);
+        let target = bezier(&control, 0.3);
+        let down = Ray::new(target + vec3(0, 0, 4), vec3(0, 0, -2), 0.0);
+        let rec = strand.hit(&down, 0.001, f64::MAX).unwrap();
+        assert!((rec.u - 0.3).abs() < 1e-2);
+        assert!((rec.p - target).length() < 0.03);
+        let expected = bezier_derivative(&control, 0.3).unit_vector();
+        assert!(dot(rec.tangent.unwrap(), expected) > 0.999);
+
+        let whole = strand.bounding_box(0.0, 0.0).unwrap();
+        let pieces = strand.segments(4);
+        let hits: Vec<HitRecord> = pieces
+            .iter()
+            .filter_map(|piece| {
+                let b = piece.bounding_box(0.0, 0.0).unwrap();
+                assert!(b.min.x() >= whole.min.x() && b.max.y() <= whole.max.y());
+                piece.hit(&down, 0.001, f64::MAX)
+            })
+            .collect();
+        assert_eq!(hits.len(), 1);
+        assert!((hits[0].t - rec.t).abs() < 1e-3);
+        assert!((hits[0].u - rec.u).abs() < 1e-3);
+    }
+}
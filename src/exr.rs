@@ -0,0 +1,119 @@
+//!
+//! Writing uncompressed scanline OpenEXR images with 32 bit float channels
+use std::io;
+use std::io::Write;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const FLOAT: i32 = 2;
+
+fn attribute<W: Write>(w: &mut W, name: &str, kind: &str, value: &[u8]) -> io::Result<()> {
+    w.write_all(name.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(kind.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(&(value.len() as i32).to_le_bytes())?;
+    w.write_all(value)
+}
+
+fn box2i(width: usize, height: usize) -> Vec<u8> {
+    let mut v = vec![];
+    for n in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        v.extend_from_slice(&n.to_le_bytes());
+    }
+    v
+}
+
+fn header(width: usize, height: usize) -> io::Result<Vec<u8>> {
+    let mut h = vec![];
+    h.extend_from_slice(&MAGIC);
+    h.extend_from_slice(&2i32.to_le_bytes());
+
+    // channels have to be listed in alphabetical order
+    let mut channels = vec![];
+    for name in &["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&FLOAT.to_le_bytes());
+        channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+
+    attribute(&mut h, "channels", "chlist", &channels)?;
+    attribute(&mut h, "compression", "compression", &[0])?;
+    attribute(&mut h, "dataWindow", "box2i", &box2i(width, height))?;
+    attribute(&mut h, "displayWindow", "box2i", &box2i(width, height))?;
+    attribute(&mut h, "lineOrder", "lineOrder", &[0])?;
+    attribute(&mut h, "pixelAspectRatio", "float", &1f32.to_le_bytes())?;
+    let mut center = 0f32.to_le_bytes().to_vec();
+    center.extend_from_slice(&0f32.to_le_bytes());
+    attribute(&mut h, "screenWindowCenter", "v2f", &center)?;
+    attribute(&mut h, "screenWindowWidth", "float", &1f32.to_le_bytes())?;
+    h.push(0);
+    Ok(h)
+}
+
+/// Writes `pixels` (row by row, top first) as a single part scanline image,
+/// one scanline per chunk.
+pub fn encode_exr<W: Write>(
+    w: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[[f32; 3]],
+) -> io::Result<()> {
+    let header = header(width, height)?;
+    w.write_all(&header)?;
+
+    let line_bytes = width * 3 * 4;
+    let chunk_bytes = 8 + line_bytes;
+    let table_end = header.len() + height * 8;
+    for y in 0..height {
+        let offset = (table_end + y * chunk_bytes) as u64;
+        w.write_all(&offset.to_le_bytes())?;
+    }
+
+    for (y, row) in pixels.chunks(width).take(height).enumerate() {
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_bytes as i32).to_le_bytes())?;
+        // channel planes in B, G, R order
+        for c in (0..3).rev() {
+            for px in row {
+                w.write_all(&px[c].to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_f32(data: &[u8], at: usize) -> f32 {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(&data[at..at + 4]);
+        f32::from_le_bytes(b)
+    }
+
+    #[test]
+    fn lays_out_offsets_and_planes() {
+        let pixels = vec![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]];
+        let mut data = vec![];
+        encode_exr(&mut data, 1, 2, &pixels).unwrap();
+        assert_eq!(&data[..4], &MAGIC);
+
+        let header_len = header(1, 2).unwrap().len();
+        let mut b = [0u8; 8];
+        b.copy_from_slice(&data[header_len + 8..header_len + 16]);
+        let second = u64::from_le_bytes(b) as usize;
+        assert_eq!(second, header_len + 16 + 20);
+        assert_eq!(data.len(), second + 20);
+
+        // second scanline: y, size, then B, G, R
+        assert_eq!(data[second], 1);
+        assert_eq!(read_f32(&data, second + 8), 6.0);
+        assert_eq!(read_f32(&data, second + 16), 4.0);
+    }
+}
@@ -0,0 +1,126 @@
+//!
+//! The floating point film renders accumulate into, and the image formats it
+//! can be written out as
+use exr::encode_exr;
+use hdr::encode_hdr;
+use png;
+use png::HasParameters;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+use tonemap::ToneMapper;
+use vec3::Vec3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    /// 8 bit sRGB PNG, tone mapped.
+    Png,
+    /// Radiance RGBE, keeping the full dynamic range.
+    Hdr,
+    /// Uncompressed 32 bit float OpenEXR.
+    Exr,
+}
+
+impl ImageFormat {
+    /// Picks a format from the file extension, ignoring case.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<ImageFormat> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "hdr" | "pic" => Some(ImageFormat::Hdr),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+/// Linear RGB pixels stored row by row, top row first.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![
+                [0.0; 3];
+                (width as usize)
+                    .checked_mul(height as usize)
+                    .expect("image too large")
+            ],
+        }
+    }
+
+    pub fn to_pixel(col: Vec3) -> [f32; 3] {
+        [col.x() as f32, col.y() as f32, col.z() as f32]
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Vec3 {
+        let [r, g, b] = self.pixels[y as usize * self.width as usize + x as usize];
+        Vec3::new([f64::from(r), f64::from(g), f64::from(b)])
+    }
+
+    /// Tone maps every pixel to 8 bit sRGB.
+    pub fn to_rgb8(&self, tone: &ToneMapper) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.pixels.len() * 3);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                out.extend_from_slice(&tone.to_srgb8(self.get(x, y)));
+            }
+        }
+        out
+    }
+
+    pub fn write_png<P: AsRef<Path>>(&self, path: P, tone: &ToneMapper) -> io::Result<()> {
+        let file = File::create(path)?;
+        let w = &mut BufWriter::new(file);
+
+        let mut encoder = png::Encoder::new(w, self.width, self.height);
+        encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb8(tone))?;
+        Ok(())
+    }
+
+    pub fn write_hdr<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        encode_hdr(
+            &mut w,
+            self.width as usize,
+            self.height as usize,
+            &self.pixels,
+        )
+    }
+
+    pub fn write_exr<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        encode_exr(
+            &mut w,
+            self.width as usize,
+            self.height as usize,
+            &self.pixels,
+        )
+    }
+
+    /// `tone` only applies to formats with a limited range; the HDR formats
+    /// get the raw film values.
+    pub fn write<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: ImageFormat,
+        tone: &ToneMapper,
+    ) -> io::Result<()> {
+        match format {
+            ImageFormat::Png => self.write_png(path, tone),
+            ImageFormat::Hdr => self.write_hdr(path),
+            ImageFormat::Exr => self.write_exr(path),
+        }
+    }
+}
@@ -1,27 +1,79 @@
-use bvh::{surrounding_box, AABB};
+use bvh::{sah_split, surrounding_box, AABB};
 use material::Material;
 use rand::prelude::*;
 use std::cmp::Ordering;
+use std::f64;
+use std::f64::consts::PI;
 use std::fmt::Debug;
+use std::iter;
+use std::mem;
 use std::sync::Arc;
-use vec3::{vec3, Ray, Vec3};
+use vec3::{dot, vec3, Onb, Ray, Vec3};
 
 #[derive(Clone)]
 pub struct HitRecord {
     pub t: f64,
     pub p: Vec3,
     pub normal: Vec3,
-    pub material: Arc<Material>,
+    /// Surface coordinates of the hit point, each in [0, 1] for most shapes.
+    pub u: f64,
+    pub v: f64,
+    /// Direction along the fibre at the hit point, for shapes such as curves
+    /// whose materials shade relative to it.
+    pub tangent: Option<Vec3>,
+    /// Colour interpolated from a mesh's vertices, which `Lambertian` uses in
+    /// place of its own albedo.
+    pub color: Option<Vec3>,
+    pub material: Arc<dyn Material>,
 }
 
-pub trait Hittable: Sync + Debug {
+/// Longitude/latitude coordinates of a point on the unit sphere.
+pub fn sphere_uv(p: Vec3) -> (f64, f64) {
+    let phi = p.z().atan2(p.x());
+    let theta = p.y().clamp(-1.0, 1.0).asin();
+    (1.0 - (phi + PI) / (2.0 * PI), (theta + PI / 2.0) / PI)
+}
+
+pub trait Hittable: Sync + Send + Debug {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB>;
-    fn box_clone(&self) -> Box<Hittable>;
+    fn box_clone(&self) -> Box<dyn Hittable>;
+
+    /// The solid angle density, seen from `origin`, with which
+    /// `random_direction` picks `direction`. Shapes that can't be sampled as
+    /// lights return 0.
+    fn pdf_value(&self, _origin: Vec3, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    /// A direction from `origin` towards a random point on the shape.
+    fn random_direction(&self, _origin: Vec3) -> Vec3 {
+        vec3(1, 0, 0)
+    }
+
+    /// Whether `pdf_value` and `random_direction` are implemented, so that
+    /// the shape can be put in the list of lights.
+    fn is_sampleable(&self) -> bool {
+        false
+    }
+
+    /// Every point in `(t_min, t_max)` where the ray crosses the surface,
+    /// nearest first. For closed shapes with outward normals, a normal facing
+    /// the ray marks an entry and one facing away an exit.
+    fn crossings(&self, r: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord> {
+        let mut hits = vec![];
+        let mut t = t_min;
+        while let Some(rec) = self.hit(r, t, t_max) {
+            // step past the hit so the same root isn't found again
+            t = rec.t + 1e-7 * (1.0 + rec.t.abs());
+            hits.push(rec);
+        }
+        hits
+    }
 }
 
-impl Clone for Box<Hittable> {
-    fn clone(&self) -> Box<Hittable> {
+impl Clone for Box<dyn Hittable> {
+    fn clone(&self) -> Box<dyn Hittable> {
         self.box_clone()
     }
 }
@@ -30,11 +82,11 @@ impl Clone for Box<Hittable> {
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f64,
-    pub material: Arc<Material>,
+    pub material: Arc<dyn Material>,
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f64, material: Arc<Material>) -> Sphere {
+    pub fn new(center: Vec3, radius: f64, material: Arc<dyn Material>) -> Sphere {
         Sphere {
             center,
             radius,
@@ -56,12 +108,17 @@ impl Hittable for Sphere {
                 let t = temp_minus;
                 let p = r.point_at_parameter(t);
                 let normal = (p - self.center) / self.radius;
+                let (u, v) = sphere_uv((p - self.center) / self.radius.abs());
 
                 return Some(HitRecord {
                     t,
                     p,
                     normal,
+                    u,
+                    v,
                     material: Arc::clone(&self.material),
+                    tangent: None,
+                    color: None,
                 });
             }
 
@@ -70,12 +127,17 @@ impl Hittable for Sphere {
                 let t = temp_plus;
                 let p = r.point_at_parameter(temp_plus);
                 let normal = (p - self.center) / self.radius;
+                let (u, v) = sphere_uv((p - self.center) / self.radius.abs());
 
                 return Some(HitRecord {
                     t,
                     p,
                     normal,
+                    u,
+                    v,
                     material: Arc::clone(&self.material),
+                    tangent: None,
+                    color: None,
                 });
             }
             None
@@ -91,9 +153,50 @@ impl Hittable for Sphere {
         })
     }
 
-    fn box_clone(&self) -> Box<Hittable> {
+    fn box_clone(&self) -> Box<dyn Hittable> {
         Box::new((*self).clone())
     }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        if self
+            .hit(&Ray::new(origin, direction, 0.0), 0.001, f64::MAX)
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        let dist_squared = (self.center - origin).squared_length();
+        let r_squared = self.radius * self.radius;
+        if dist_squared <= r_squared {
+            // from inside, every direction hits the sphere
+            return 1.0 / (4.0 * PI);
+        }
+        let cos_theta_max = (1.0 - r_squared / dist_squared).sqrt();
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
+
+    fn random_direction(&self, origin: Vec3) -> Vec3 {
+        let direction = self.center - origin;
+        let dist_squared = direction.squared_length();
+        let r_squared = self.radius * self.radius;
+        if dist_squared <= r_squared {
+            return Vec3::random_unit_vector();
+        }
+
+        // uniformly sample the cone of directions subtended by the sphere
+        let mut rng = thread_rng();
+        let r1: f64 = rng.gen();
+        let r2: f64 = rng.gen();
+        let cos_theta_max = (1.0 - r_squared / dist_squared).sqrt();
+        let z = 1.0 + r2 * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * r1;
+        let sin_theta = (1.0 - z * z).sqrt();
+        Onb::from_w(direction).local(vec3(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
+    }
+
+    fn is_sampleable(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -103,7 +206,7 @@ pub struct MovingSphere {
     pub time0: f64,
     pub time1: f64,
     pub radius: f64,
-    pub material: Arc<Material>,
+    pub material: Arc<dyn Material>,
 }
 
 impl MovingSphere {
@@ -113,7 +216,7 @@ impl MovingSphere {
         time0: f64,
         time1: f64,
         radius: f64,
-        material: Arc<Material>,
+        material: Arc<dyn Material>,
     ) -> MovingSphere {
         MovingSphere {
             center0,
@@ -144,12 +247,17 @@ impl Hittable for MovingSphere {
                 let t = temp_minus;
                 let p = r.point_at_parameter(t);
                 let normal = (p - self.center(r.time())) / self.radius;
+                let (u, v) = sphere_uv((p - self.center(r.time())) / self.radius.abs());
 
                 return Some(HitRecord {
                     t,
                     p,
                     normal,
+                    u,
+                    v,
                     material: Arc::clone(&self.material),
+                    tangent: None,
+                    color: None,
                 });
             }
 
@@ -158,12 +266,17 @@ impl Hittable for MovingSphere {
                 let t = temp_plus;
                 let p = r.point_at_parameter(temp_plus);
                 let normal = (p - self.center(r.time())) / self.radius;
+                let (u, v) = sphere_uv((p - self.center(r.time())) / self.radius.abs());
 
                 return Some(HitRecord {
                     t,
                     p,
                     normal,
+                    u,
+                    v,
                     material: Arc::clone(&self.material),
+                    tangent: None,
+                    color: None,
                 });
             }
             None
@@ -186,14 +299,993 @@ impl Hittable for MovingSphere {
         Some(surrounding_box(&b0, &b1))
     }
 
-    fn box_clone(&self) -> Box<Hittable> {
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+/// A single triangle. The geometric normal follows the winding order, so
+/// `(v1 - v0) x (v2 - v0)` points out of the front face.
+#[derive(Clone, Debug)]
+pub struct Triangle {
+    pub vertices: [Vec3; 3],
+    /// Per-vertex shading normals, interpolated across the face.
+    pub normals: Option<[Vec3; 3]>,
+    /// Per-vertex texture coordinates; without them the hit record carries
+    /// the barycentric coordinates of `v1` and `v2`.
+    pub uvs: Option<[(f64, f64); 3]>,
+    pub material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(v0: Vec3, v1: Vec3, v2: Vec3, material: Arc<dyn Material>) -> Triangle {
+        Triangle {
+            vertices: [v0, v1, v2],
+            normals: None,
+            uvs: None,
+            material,
+        }
+    }
+
+    pub fn with_normals(self, normals: [Vec3; 3]) -> Triangle {
+        Triangle {
+            normals: Some(normals),
+            ..self
+        }
+    }
+
+    pub fn with_uvs(self, uvs: [(f64, f64); 3]) -> Triangle {
+        Triangle {
+            uvs: Some(uvs),
+            ..self
+        }
+    }
+}
+
+/// Möller-Trumbore intersection, returning `(t, b1, b2)` where `b1` and `b2`
+/// are the barycentric weights of the second and third vertex.
+pub fn intersect_triangle(
+    r: &Ray,
+    v0: Vec3,
+    v1: Vec3,
+    v2: Vec3,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let e1 = v1 - v0;
+    let e2 = v2 - v0;
+    let pvec = r.direction().cross(e2);
+    let det = e1.dot(pvec);
+    // parallel to the plane, or a degenerate triangle
+    if det.abs() < 1e-12 * e1.length() * e2.length() * r.direction().length() {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = r.origin() - v0;
+    let b1 = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let qvec = tvec.cross(e1);
+    let b2 = r.direction().dot(qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = e2.dot(qvec) * inv_det;
+    if t < t_max && t > t_min {
+        Some((t, b1, b2))
+    } else {
+        None
+    }
+}
+
+/// Bounds of a set of points, padded so that flat boxes still have volume.
+pub fn points_bounding_box(points: &[Vec3]) -> AABB {
+    const PAD: f64 = 1e-4;
+    let mut min = points[0];
+    let mut max = points[0];
+    for p in &points[1..] {
+        min = min.apply_per_element(*p, f64::min);
+        max = max.apply_per_element(*p, f64::max);
+    }
+    let pad = (max - min).map(|extent| if extent < PAD { PAD / 2.0 } else { 0.0 });
+    AABB {
+        min: min - pad,
+        max: max + pad,
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let [v0, v1, v2] = self.vertices;
+        let (t, b1, b2) = intersect_triangle(r, v0, v1, v2, t_min, t_max)?;
+        let b0 = 1.0 - b1 - b2;
+
+        let normal = match self.normals {
+            Some([n0, n1, n2]) => (b0 * n0 + b1 * n1 + b2 * n2).unit_vector(),
+            None => (v1 - v0).cross(v2 - v0).unit_vector(),
+        };
+        let (u, v) = match self.uvs {
+            Some([uv0, uv1, uv2]) => (
+                b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+                b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+            ),
+            None => (b1, b2),
+        };
+
+        Some(HitRecord {
+            t,
+            p: r.point_at_parameter(t),
+            normal,
+            u,
+            v,
+            material: Arc::clone(&self.material),
+            tangent: None,
+            color: None,
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(points_bounding_box(&self.vertices))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+/// A rectangle perpendicular to the `k` axis, spanning `[a0, a1]` on axis `a`
+/// and `[b0, b1]` on axis `b`. Shared by the three axis-aligned rectangles.
+#[derive(Clone, Copy, Debug)]
+struct AxisRect {
+    axes: (usize, usize, usize),
+    a0: f64,
+    a1: f64,
+    b0: f64,
+    b1: f64,
+    k: f64,
+}
+
+impl AxisRect {
+    fn point(&self, a: f64, b: f64) -> Vec3 {
+        let (ai, bi, ki) = self.axes;
+        let mut p = [0.0; 3];
+        p[ai] = a;
+        p[bi] = b;
+        p[ki] = self.k;
+        Vec3::new(p)
+    }
+
+    fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        material: &Arc<dyn Material>,
+    ) -> Option<HitRecord> {
+        let (ai, bi, ki) = self.axes;
+        let t = (self.k - r.origin()[ki]) / r.direction()[ki];
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+        let a = r.origin()[ai] + t * r.direction()[ai];
+        let b = r.origin()[bi] + t * r.direction()[bi];
+        if a < self.a0 || a > self.a1 || b < self.b0 || b > self.b1 {
+            return None;
+        }
+
+        let mut normal = [0.0; 3];
+        normal[ki] = 1.0;
+        Some(HitRecord {
+            t,
+            p: r.point_at_parameter(t),
+            normal: Vec3::new(normal),
+            u: (a - self.a0) / (self.a1 - self.a0),
+            v: (b - self.b0) / (self.b1 - self.b0),
+            material: Arc::clone(material),
+            tangent: None,
+            color: None,
+        })
+    }
+
+    fn bounding_box(&self) -> AABB {
+        // pad the flat axis so the box has some thickness
+        let (_, _, ki) = self.axes;
+        let mut pad = [0.0; 3];
+        pad[ki] = 0.0001;
+        let pad = Vec3::new(pad);
+        AABB {
+            min: self.point(self.a0, self.b0) - pad,
+            max: self.point(self.a1, self.b1) + pad,
+        }
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3, material: &Arc<dyn Material>) -> f64 {
+        let rec = match self.hit(&Ray::new(origin, direction, 0.0), 0.001, f64::MAX, material) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+        let area = (self.a1 - self.a0) * (self.b1 - self.b0);
+        let dist_squared = rec.t * rec.t * direction.squared_length();
+        let cosine = (direction.dot(rec.normal) / direction.length()).abs();
+        dist_squared / (cosine * area)
+    }
+
+    fn random_direction(&self, origin: Vec3) -> Vec3 {
+        let mut rng = thread_rng();
+        let a = self.a0 + rng.gen::<f64>() * (self.a1 - self.a0);
+        let b = self.b0 + rng.gen::<f64>() * (self.b1 - self.b0);
+        self.point(a, b) - origin
+    }
+}
+
+/// A rectangle in the plane `z = k`, facing +z.
+#[derive(Clone, Debug)]
+pub struct XYRect {
+    pub x0: f64,
+    pub x1: f64,
+    pub y0: f64,
+    pub y1: f64,
+    pub k: f64,
+    pub material: Arc<dyn Material>,
+}
+
+impl XYRect {
+    fn rect(&self) -> AxisRect {
+        AxisRect {
+            axes: (0, 1, 2),
+            a0: self.x0,
+            a1: self.x1,
+            b0: self.y0,
+            b1: self.y1,
+            k: self.k,
+        }
+    }
+}
+
+impl Hittable for XYRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.rect().hit(r, t_min, t_max, &self.material)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.rect().bounding_box())
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        self.rect().pdf_value(origin, direction, &self.material)
+    }
+
+    fn random_direction(&self, origin: Vec3) -> Vec3 {
+        self.rect().random_direction(origin)
+    }
+
+    fn is_sampleable(&self) -> bool {
+        true
+    }
+}
+
+/// A rectangle in the plane `y = k`, facing +y.
+#[derive(Clone, Debug)]
+pub struct XZRect {
+    pub x0: f64,
+    pub x1: f64,
+    pub z0: f64,
+    pub z1: f64,
+    pub k: f64,
+    pub material: Arc<dyn Material>,
+}
+
+impl XZRect {
+    fn rect(&self) -> AxisRect {
+        AxisRect {
+            axes: (0, 2, 1),
+            a0: self.x0,
+            a1: self.x1,
+            b0: self.z0,
+            b1: self.z1,
+            k: self.k,
+        }
+    }
+}
+
+impl Hittable for XZRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.rect().hit(r, t_min, t_max, &self.material)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.rect().bounding_box())
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        self.rect().pdf_value(origin, direction, &self.material)
+    }
+
+    fn random_direction(&self, origin: Vec3) -> Vec3 {
+        self.rect().random_direction(origin)
+    }
+
+    fn is_sampleable(&self) -> bool {
+        true
+    }
+}
+
+/// A rectangle in the plane `x = k`, facing +x.
+#[derive(Clone, Debug)]
+pub struct YZRect {
+    pub y0: f64,
+    pub y1: f64,
+    pub z0: f64,
+    pub z1: f64,
+    pub k: f64,
+    pub material: Arc<dyn Material>,
+}
+
+impl YZRect {
+    fn rect(&self) -> AxisRect {
+        AxisRect {
+            axes: (1, 2, 0),
+            a0: self.y0,
+            a1: self.y1,
+            b0: self.z0,
+            b1: self.z1,
+            k: self.k,
+        }
+    }
+}
+
+impl Hittable for YZRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.rect().hit(r, t_min, t_max, &self.material)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.rect().bounding_box())
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        self.rect().pdf_value(origin, direction, &self.material)
+    }
+
+    fn random_direction(&self, origin: Vec3) -> Vec3 {
+        self.rect().random_direction(origin)
+    }
+
+    fn is_sampleable(&self) -> bool {
+        true
+    }
+}
+
+/// Reverses the normals of the wrapped shape, e.g. to make a rectangle face
+/// the other way.
+#[derive(Clone, Debug)]
+pub struct FlipNormals {
+    pub inner: Box<dyn Hittable>,
+}
+
+impl Hittable for FlipNormals {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.inner.hit(r, t_min, t_max).map(|rec| HitRecord {
+            normal: -rec.normal,
+            ..rec
+        })
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.inner.bounding_box(t0, t1)
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        self.inner.pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Vec3) -> Vec3 {
+        self.inner.random_direction(origin)
+    }
+
+    fn is_sampleable(&self) -> bool {
+        self.inner.is_sampleable()
+    }
+}
+
+/// An axis-aligned box made of six rectangles with outward normals.
+#[derive(Clone, Debug)]
+pub struct BoxShape {
+    pub min: Vec3,
+    pub max: Vec3,
+    sides: HittableList,
+}
+
+impl BoxShape {
+    pub fn new(min: Vec3, max: Vec3, material: Arc<dyn Material>) -> BoxShape {
+        let m = || Arc::clone(&material);
+        let flip = |inner: Box<dyn Hittable>| Box::new(FlipNormals { inner }) as Box<dyn Hittable>;
+        let list: Vec<Box<dyn Hittable>> = vec![
+            Box::new(XYRect {
+                x0: min.x(),
+                x1: max.x(),
+                y0: min.y(),
+                y1: max.y(),
+                k: max.z(),
+                material: m(),
+            }),
+            flip(Box::new(XYRect {
+                x0: min.x(),
+                x1: max.x(),
+                y0: min.y(),
+                y1: max.y(),
+                k: min.z(),
+                material: m(),
+            })),
+            Box::new(XZRect {
+                x0: min.x(),
+                x1: max.x(),
+                z0: min.z(),
+                z1: max.z(),
+                k: max.y(),
+                material: m(),
+            }),
+            flip(Box::new(XZRect {
+                x0: min.x(),
+                x1: max.x(),
+                z0: min.z(),
+                z1: max.z(),
+                k: min.y(),
+                material: m(),
+            })),
+            Box::new(YZRect {
+                y0: min.y(),
+                y1: max.y(),
+                z0: min.z(),
+                z1: max.z(),
+                k: max.x(),
+                material: m(),
+            }),
+            flip(Box::new(YZRect {
+                y0: min.y(),
+                y1: max.y(),
+                z0: min.z(),
+                z1: max.z(),
+                k: min.x(),
+                material: m(),
+            })),
+        ];
+        BoxShape {
+            min,
+            max,
+            sides: HittableList { list },
+        }
+    }
+}
+
+impl Hittable for BoxShape {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.sides.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(AABB {
+            min: self.min,
+            max: self.max,
+        })
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        self.sides.pdf_value(origin, direction)
+    }
+
+    fn random_direction(&self, origin: Vec3) -> Vec3 {
+        self.sides.random_direction(origin)
+    }
+
+    fn is_sampleable(&self) -> bool {
+        true
+    }
+}
+
+/// The real roots of `a t^2 + b t + c`, smallest first. Degenerates to the
+/// linear case when `a` is (nearly) zero.
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return None;
+        }
+        let t = -c / b;
+        return Some((t, t));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // avoids cancellation when b is large
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (t0, t1) = if q == 0.0 { (0.0, 0.0) } else { (q / a, c / q) };
+    Some((t0.min(t1), t0.max(t1)))
+}
+
+/// The angle around the y axis, mapped to `[0, 1]`.
+fn azimuth_u(x: f64, z: f64) -> f64 {
+    (z.atan2(x) + PI) / (2.0 * PI)
+}
+
+/// Turns `normal` towards the side the ray came from, for open surfaces
+/// that have no inside.
+fn face_forward(normal: Vec3, r: &Ray) -> Vec3 {
+    if dot(normal, r.direction()) > 0.0 {
+        -normal
+    } else {
+        normal
+    }
+}
+
+/// The closest of several candidate intersections, in object coordinates
+/// relative to the shape's `center`.
+struct Nearest {
+    t: f64,
+    normal: Vec3,
+    u: f64,
+    v: f64,
+}
+
+impl Nearest {
+    fn consider(best: &Option<Nearest>, t: f64, t_min: f64, t_max: f64) -> bool {
+        let closer = match *best {
+            Some(ref b) => t < b.t,
+            None => true,
+        };
+        t > t_min && t < t_max && closer
+    }
+
+    fn into_record(self, r: &Ray, material: &Arc<dyn Material>) -> HitRecord {
+        HitRecord {
+            t: self.t,
+            p: r.point_at_parameter(self.t),
+            normal: self.normal,
+            u: self.u,
+            v: self.v,
+            material: Arc::clone(material),
+            tangent: None,
+            color: None,
+        }
+    }
+}
+
+/// Tries the cap disk at height `y` (relative to the base) with the given
+/// normal.
+#[allow(clippy::too_many_arguments)]
+fn hit_cap(
+    best: &mut Option<Nearest>,
+    o: Vec3,
+    d: Vec3,
+    y: f64,
+    radius: f64,
+    normal: Vec3,
+    t_min: f64,
+    t_max: f64,
+) {
+    if d.y() == 0.0 {
+        return;
+    }
+    let t = (y - o.y()) / d.y();
+    let (x, z) = (o.x() + t * d.x(), o.z() + t * d.z());
+    let dist_squared = x * x + z * z;
+    if dist_squared <= radius * radius && Nearest::consider(best, t, t_min, t_max) {
+        *best = Some(Nearest {
+            t,
+            normal,
+            u: azimuth_u(x, z),
+            v: dist_squared.sqrt() / radius,
+        });
+    }
+}
+
+/// Bounds of a shape of revolution around the y axis from `center` up to
+/// `height`.
+fn revolution_box(center: Vec3, radius: f64, height: f64) -> AABB {
+    points_bounding_box(&[
+        center - vec3(radius, 0.0, radius),
+        center + vec3(radius, height, radius),
+    ])
+}
+
+/// A cylinder around the y axis, from `center` up to `center.y + height`.
+/// Use `Transformed` to orient it differently.
+#[derive(Clone, Debug)]
+pub struct Cylinder {
+    pub center: Vec3,
+    pub radius: f64,
+    pub height: f64,
+    /// Closes the ends with disks; open tubes report normals facing the ray.
+    pub capped: bool,
+    pub material: Arc<dyn Material>,
+}
+
+impl Cylinder {
+    pub fn new(
+        center: Vec3,
+        radius: f64,
+        height: f64,
+        capped: bool,
+        material: Arc<dyn Material>,
+    ) -> Cylinder {
+        Cylinder {
+            center,
+            radius,
+            height,
+            capped,
+            material,
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let o = r.origin() - self.center;
+        let d = r.direction();
+        let mut best = None;
+
+        let a = d.x() * d.x() + d.z() * d.z();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z());
+        let c = o.x() * o.x() + o.z() * o.z() - self.radius * self.radius;
+        if a > 0.0 {
+            if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+                for &t in &[t0, t1] {
+                    let p = o + t * d;
+                    if p.y() >= 0.0
+                        && p.y() <= self.height
+                        && Nearest::consider(&best, t, t_min, t_max)
+                    {
+                        let normal = vec3(p.x(), 0.0, p.z()) / self.radius;
+                        best = Some(Nearest {
+                            t,
+                            normal,
+                            u: azimuth_u(p.x(), p.z()),
+                            v: p.y() / self.height,
+                        });
+                    }
+                }
+            }
+        }
+
+        if self.capped {
+            hit_cap(
+                &mut best,
+                o,
+                d,
+                0.0,
+                self.radius,
+                vec3(0, -1, 0),
+                t_min,
+                t_max,
+            );
+            hit_cap(
+                &mut best,
+                o,
+                d,
+                self.height,
+                self.radius,
+                vec3(0, 1, 0),
+                t_min,
+                t_max,
+            );
+        }
+
+        best.map(|mut n| {
+            if !self.capped {
+                n.normal = face_forward(n.normal, r);
+            }
+            n.into_record(r, &self.material)
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(revolution_box(self.center, self.radius, self.height))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+/// A cone around the y axis with its base circle at `center` and its apex
+/// `height` above it.
+#[derive(Clone, Debug)]
+pub struct Cone {
+    pub center: Vec3,
+    pub radius: f64,
+    pub height: f64,
+    /// Closes the base with a disk; open cones report normals facing the ray.
+    pub capped: bool,
+    pub material: Arc<dyn Material>,
+}
+
+impl Cone {
+    pub fn new(
+        center: Vec3,
+        radius: f64,
+        height: f64,
+        capped: bool,
+        material: Arc<dyn Material>,
+    ) -> Cone {
+        Cone {
+            center,
+            radius,
+            height,
+            capped,
+            material,
+        }
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let o = r.origin() - self.center;
+        let d = r.direction();
+        let mut best = None;
+
+        // x^2 + z^2 = k^2 (h - y)^2
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let s0 = self.height - o.y();
+        let a = d.x() * d.x() + d.z() * d.z() - k2 * d.y() * d.y();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z()) + 2.0 * k2 * s0 * d.y();
+        let c = o.x() * o.x() + o.z() * o.z() - k2 * s0 * s0;
+        if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+            for &t in &[t0, t1] {
+                let p = o + t * d;
+                if p.y() >= 0.0 && p.y() <= self.height && Nearest::consider(&best, t, t_min, t_max)
+                {
+                    let normal = vec3(p.x(), k2 * (self.height - p.y()), p.z());
+                    let normal = if normal.squared_length() > 0.0 {
+                        normal.unit_vector()
+                    } else {
+                        vec3(0, 1, 0)
+                    };
+                    best = Some(Nearest {
+                        t,
+                        normal,
+                        u: azimuth_u(p.x(), p.z()),
+                        v: p.y() / self.height,
+                    });
+                }
+            }
+        }
+
+        if self.capped {
+            hit_cap(
+                &mut best,
+                o,
+                d,
+                0.0,
+                self.radius,
+                vec3(0, -1, 0),
+                t_min,
+                t_max,
+            );
+        }
+
+        best.map(|mut n| {
+            if !self.capped {
+                n.normal = face_forward(n.normal, r);
+            }
+            n.into_record(r, &self.material)
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(revolution_box(self.center, self.radius, self.height))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+/// A ring in the plane `y = center.y` between two radii. With an inner radius
+/// of zero it is a disk. Normals face the ray.
+#[derive(Clone, Debug)]
+pub struct Annulus {
+    pub center: Vec3,
+    pub inner_radius: f64,
+    pub outer_radius: f64,
+    pub material: Arc<dyn Material>,
+}
+
+impl Annulus {
+    pub fn new(
+        center: Vec3,
+        inner_radius: f64,
+        outer_radius: f64,
+        material: Arc<dyn Material>,
+    ) -> Annulus {
+        Annulus {
+            center,
+            inner_radius,
+            outer_radius,
+            material,
+        }
+    }
+}
+
+impl Annulus {
+    fn hit_ring(
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        center: Vec3,
+        radii: (f64, f64),
+        material: &Arc<dyn Material>,
+    ) -> Option<HitRecord> {
+        let (inner, outer) = radii;
+        let o = r.origin() - center;
+        let d = r.direction();
+        if d.y() == 0.0 {
+            return None;
+        }
+        let t = -o.y() / d.y();
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+        let (x, z) = (o.x() + t * d.x(), o.z() + t * d.z());
+        let dist = (x * x + z * z).sqrt();
+        if dist < inner || dist > outer {
+            return None;
+        }
+        Some(
+            Nearest {
+                t,
+                normal: face_forward(vec3(0, 1, 0), r),
+                u: azimuth_u(x, z),
+                v: (dist - inner) / (outer - inner),
+            }
+            .into_record(r, material),
+        )
+    }
+}
+
+impl Hittable for Annulus {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        Annulus::hit_ring(
+            r,
+            t_min,
+            t_max,
+            self.center,
+            (self.inner_radius, self.outer_radius),
+            &self.material,
+        )
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(revolution_box(self.center, self.outer_radius, 0.0))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+/// A flat disk in the plane `y = center.y`. Normals face the ray.
+#[derive(Clone, Debug)]
+pub struct Disk {
+    pub center: Vec3,
+    pub radius: f64,
+    pub material: Arc<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, radius: f64, material: Arc<dyn Material>) -> Disk {
+        Disk {
+            center,
+            radius,
+            material,
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        Annulus::hit_ring(
+            r,
+            t_min,
+            t_max,
+            self.center,
+            (0.0, self.radius),
+            &self.material,
+        )
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(revolution_box(self.center, self.radius, 0.0))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+/// A dish `y = height * (x^2 + z^2) / radius^2` with its vertex at `center`,
+/// open at the top where it reaches `radius`. Normals face the ray.
+#[derive(Clone, Debug)]
+pub struct Paraboloid {
+    pub center: Vec3,
+    pub radius: f64,
+    pub height: f64,
+    pub material: Arc<dyn Material>,
+}
+
+impl Paraboloid {
+    pub fn new(center: Vec3, radius: f64, height: f64, material: Arc<dyn Material>) -> Paraboloid {
+        Paraboloid {
+            center,
+            radius,
+            height,
+            material,
+        }
+    }
+}
+
+impl Hittable for Paraboloid {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let o = r.origin() - self.center;
+        let d = r.direction();
+        let q = self.height / (self.radius * self.radius);
+        let a = q * (d.x() * d.x() + d.z() * d.z());
+        let b = 2.0 * q * (o.x() * d.x() + o.z() * d.z()) - d.y();
+        let c = q * (o.x() * o.x() + o.z() * o.z()) - o.y();
+
+        let (t0, t1) = solve_quadratic(a, b, c)?;
+        let mut best = None;
+        for &t in &[t0, t1] {
+            let p = o + t * d;
+            if p.y() <= self.height && Nearest::consider(&best, t, t_min, t_max) {
+                let normal = vec3(2.0 * q * p.x(), -1.0, 2.0 * q * p.z()).unit_vector();
+                best = Some(Nearest {
+                    t,
+                    normal: face_forward(normal, r),
+                    u: azimuth_u(p.x(), p.z()),
+                    v: p.y() / self.height,
+                });
+            }
+        }
+        best.map(|n| n.into_record(r, &self.material))
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(revolution_box(self.center, self.radius, self.height))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
         Box::new((*self).clone())
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct HittableList {
-    pub list: Vec<Box<Hittable>>,
+    pub list: Vec<Box<dyn Hittable>>,
 }
 
 impl Hittable for HittableList {
@@ -202,13 +1294,9 @@ impl Hittable for HittableList {
         let mut closest_so_far = t_max;
 
         for item in &self.list {
-            match item.hit(&r.clone(), t_min, closest_so_far) {
-                Some(temp_rec) => {
-                    closest_so_far = temp_rec.t;
-                    hit = Some(temp_rec)
-                }
-
-                None => (),
+            if let Some(temp_rec) = item.hit(&r.clone(), t_min, closest_so_far) {
+                closest_so_far = temp_rec.t;
+                hit = Some(temp_rec)
             }
         }
 
@@ -216,7 +1304,7 @@ impl Hittable for HittableList {
     }
 
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
-        if self.list.len() < 1 {
+        if self.list.is_empty() {
             return None;
         }
 
@@ -236,12 +1324,37 @@ impl Hittable for HittableList {
         Some(tmp_box)
     }
 
-    fn box_clone(&self) -> Box<Hittable> {
+    fn box_clone(&self) -> Box<dyn Hittable> {
         Box::new((*self).clone())
     }
+
+    /// Every item is picked with equal probability, so the density is the
+    /// average of the item densities.
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        if self.list.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .list
+            .iter()
+            .map(|item| item.pdf_value(origin, direction))
+            .sum();
+        sum / self.list.len() as f64
+    }
+
+    fn random_direction(&self, origin: Vec3) -> Vec3 {
+        let mut rng = thread_rng();
+        let idx = rng.gen_range(0, self.list.len());
+        self.list[idx].random_direction(origin)
+    }
+
+    fn is_sampleable(&self) -> bool {
+        !self.list.is_empty() && self.list.iter().all(|item| item.is_sampleable())
+    }
 }
 
-pub fn box_x_compare(a: &Box<Hittable>, b: &Box<Hittable>) -> Ordering {
+#[allow(clippy::borrowed_box)]
+pub fn box_x_compare(a: &Box<dyn Hittable>, b: &Box<dyn Hittable>) -> Ordering {
     match (a.bounding_box(0.0, 0.0), b.bounding_box(0.0, 0.0)) {
         (Some(l), Some(r)) => {
             if l.min().x() - r.min().x() < 0.0 {
@@ -254,7 +1367,8 @@ pub fn box_x_compare(a: &Box<Hittable>, b: &Box<Hittable>) -> Ordering {
     }
 }
 
-pub fn box_y_compare(a: &Box<Hittable>, b: &Box<Hittable>) -> Ordering {
+#[allow(clippy::borrowed_box)]
+pub fn box_y_compare(a: &Box<dyn Hittable>, b: &Box<dyn Hittable>) -> Ordering {
     match (a.bounding_box(0.0, 0.0), b.bounding_box(0.0, 0.0)) {
         (Some(l), Some(r)) => {
             if l.min().y() - r.min().y() < 0.0 {
@@ -267,7 +1381,8 @@ pub fn box_y_compare(a: &Box<Hittable>, b: &Box<Hittable>) -> Ordering {
     }
 }
 
-pub fn box_z_compare(a: &Box<Hittable>, b: &Box<Hittable>) -> Ordering {
+#[allow(clippy::borrowed_box)]
+pub fn box_z_compare(a: &Box<dyn Hittable>, b: &Box<dyn Hittable>) -> Ordering {
     match (a.bounding_box(0.0, 0.0), b.bounding_box(0.0, 0.0)) {
         (Some(l), Some(r)) => {
             if l.min().z() - r.min().z() < 0.0 {
@@ -286,7 +1401,8 @@ pub enum Axis {
     Z,
 }
 
-pub fn box_compare(a: &Box<Hittable>, b: &Box<Hittable>, axis: Axis) -> Ordering {
+#[allow(clippy::borrowed_box)]
+pub fn box_compare(a: &Box<dyn Hittable>, b: &Box<dyn Hittable>, axis: Axis) -> Ordering {
     match (a.bounding_box(0.0, 0.0), b.bounding_box(0.0, 0.0)) {
         (Some(l), Some(r)) => {
             let result = match axis {
@@ -306,14 +1422,125 @@ pub fn box_compare(a: &Box<Hittable>, b: &Box<Hittable>, axis: Axis) -> Ordering
 
 #[derive(Clone, Debug)]
 pub struct BVHNode {
-    left: Box<Hittable>,
-    right: Box<Hittable>,
+    left: BvhChild,
+    /// Missing only when the tree was built over a single object.
+    right: Option<BvhChild>,
     bounding_box: AABB,
 }
 
+/// Below a node is either another node or a leaf, which holds one object or
+/// a list of objects.
+#[derive(Clone, Debug)]
+enum BvhChild {
+    Node(Box<BVHNode>),
+    Leaf(Box<dyn Hittable>),
+}
+
+impl BvhChild {
+    fn hittable(&self) -> &dyn Hittable {
+        match *self {
+            BvhChild::Node(ref node) => &**node,
+            BvhChild::Leaf(ref object) => &**object,
+        }
+    }
+
+    fn leaf_count(&self) -> usize {
+        match *self {
+            BvhChild::Node(ref node) => node.leaf_count(),
+            BvhChild::Leaf(_) => 1,
+        }
+    }
+
+    fn depth(&self) -> usize {
+        match *self {
+            BvhChild::Node(ref node) => node.depth(),
+            BvhChild::Leaf(_) => 0,
+        }
+    }
+}
+
+/// How `BVHNode::with_builder` decides where to split a list of objects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BvhBuilder {
+    /// Sort along a random axis and split at the median, as `BVHNode::new`
+    /// does.
+    Median,
+    /// Split where the surface area heuristic expects the cheapest tree,
+    /// trying `bins` planes along the widest axis of the objects' centers.
+    /// Up to `max_leaf_size` objects are kept together in a leaf when
+    /// splitting them further isn't expected to pay off.
+    Sah { bins: usize, max_leaf_size: usize },
+}
+
+pub const SAH_BINS: usize = 12;
+pub const SAH_MAX_LEAF_SIZE: usize = 4;
+
+impl Default for BvhBuilder {
+    fn default() -> BvhBuilder {
+        BvhBuilder::Sah {
+            bins: SAH_BINS,
+            max_leaf_size: SAH_MAX_LEAF_SIZE,
+        }
+    }
+}
+
+/// The bounds of each object, for `bvh::sah_split`.
+fn object_boxes(objects: &[Box<dyn Hittable>], time0: f64, time1: f64) -> Vec<AABB> {
+    objects
+        .iter()
+        .map(|o| {
+            o.bounding_box(time0, time1)
+                .expect("no bounding box for BVH object")
+        })
+        .collect()
+}
+
+/// Like `Vec::split_off`, but the objects that stay are the ones `goes_left`
+/// puts on the left of a split.
+fn split_off_right(
+    objects: &mut Vec<Box<dyn Hittable>>,
+    goes_left: Vec<bool>,
+) -> Vec<Box<dyn Hittable>> {
+    let mut right = vec![];
+    for (object, goes_left) in mem::take(objects).into_iter().zip(goes_left) {
+        if goes_left {
+            objects.push(object);
+        } else {
+            right.push(object);
+        }
+    }
+    right
+}
+
+/// A subtree for `objects` built with the surface area heuristic: the object
+/// itself, a list for a leaf, or a node.
+fn sah_subtree(
+    mut objects: Vec<Box<dyn Hittable>>,
+    time0: f64,
+    time1: f64,
+    bins: usize,
+    max_leaf_size: usize,
+) -> BvhChild {
+    let len = objects.len();
+    if len == 1 {
+        return BvhChild::Leaf(objects.into_iter().next().unwrap());
+    }
+    let split = sah_split(&object_boxes(&objects, time0, time1), bins);
+    if len <= max_leaf_size && split.cost >= len as f64 {
+        return BvhChild::Leaf(Box::new(HittableList { list: objects }));
+    }
+    let right = split_off_right(&mut objects, split.left);
+    BvhChild::Node(Box::new(BVHNode::from_children(
+        sah_subtree(objects, time0, time1, bins, max_leaf_size),
+        Some(sah_subtree(right, time0, time1, bins, max_leaf_size)),
+        time0,
+        time1,
+    )))
+}
+
 impl BVHNode {
     pub fn new(
-        mut hitable: Vec<Box<Hittable>>,
+        mut hitable: Vec<Box<dyn Hittable>>,
         time0: f64,
         time1: f64,
         force_axis: &Option<Axis>,
@@ -324,52 +1551,106 @@ impl BVHNode {
             Some(Axis::Z) => hitable.sort_by(box_z_compare),
             None => {
                 let mut rng = thread_rng();
-                match rng.gen_range(0, 2) {
-                    0 => {
-                        hitable.sort_by(box_x_compare);
-                    }
-                    1 => {
-                        hitable.sort_by(box_y_compare);
-                    }
-                    2 => {
-                        hitable.sort_by(box_z_compare);
-                    }
-                    _ => panic!("this should never happen"),
+                match rng.gen_range(0usize, 3) {
+                    0 => hitable.sort_by(box_x_compare),
+                    1 => hitable.sort_by(box_y_compare),
+                    _ => hitable.sort_by(box_z_compare),
                 }
             }
         };
 
-        let left: Box<Hittable>;
-        let right: Box<Hittable>;
+        let left: BvhChild;
+        let right: Option<BvhChild>;
         let len = hitable.len();
         match len {
             0 => panic!("empty hittable list"),
             1 => {
-                left = hitable[0].clone();
-                right = hitable[0].clone();
+                left = BvhChild::Leaf(hitable.pop().unwrap());
+                right = None;
             }
             2 => {
-                left = hitable[0].clone();
-                right = hitable[1].clone();
+                right = hitable.pop().map(BvhChild::Leaf);
+                left = BvhChild::Leaf(hitable.pop().unwrap());
             }
             _ => {
                 let r = hitable.split_off(len / 2);
-                left = Box::new(BVHNode::new(hitable, time0, time1, force_axis));
-                right = Box::new(BVHNode::new(r, time0, time1, force_axis));
+                left = BvhChild::Node(Box::new(BVHNode::new(hitable, time0, time1, force_axis)));
+                right = Some(BvhChild::Node(Box::new(BVHNode::new(
+                    r, time0, time1, force_axis,
+                ))));
+            }
+        }
+
+        BVHNode::from_children(left, right, time0, time1)
+    }
+
+    /// Builds a tree over `objects` with the chosen builder.
+    pub fn with_builder(
+        mut objects: Vec<Box<dyn Hittable>>,
+        time0: f64,
+        time1: f64,
+        builder: BvhBuilder,
+    ) -> BVHNode {
+        match builder {
+            BvhBuilder::Median => BVHNode::new(objects, time0, time1, &None),
+            BvhBuilder::Sah {
+                bins,
+                max_leaf_size,
+            } => {
+                assert!(bins >= 2, "SAH needs at least two bins");
+                match objects.len() {
+                    0 => panic!("empty hittable list"),
+                    1 => BVHNode::from_children(
+                        BvhChild::Leaf(objects.pop().unwrap()),
+                        None,
+                        time0,
+                        time1,
+                    ),
+                    _ => {
+                        // the root is always split, so it has two sides
+                        let split = sah_split(&object_boxes(&objects, time0, time1), bins);
+                        let right = split_off_right(&mut objects, split.left);
+                        BVHNode::from_children(
+                            sah_subtree(objects, time0, time1, bins, max_leaf_size),
+                            Some(sah_subtree(right, time0, time1, bins, max_leaf_size)),
+                            time0,
+                            time1,
+                        )
+                    }
+                }
             }
         }
+    }
+
+    /// The number of leaves below this node.
+    pub fn leaf_count(&self) -> usize {
+        self.children().map(BvhChild::leaf_count).sum()
+    }
+
+    /// The number of nodes on the longest path from here down to a leaf.
+    pub fn depth(&self) -> usize {
+        1 + self.children().map(BvhChild::depth).max().unwrap_or(0)
+    }
+
+    fn children(&self) -> impl Iterator<Item = &BvhChild> {
+        iter::once(&self.left).chain(self.right.as_ref())
+    }
 
+    fn from_children(left: BvhChild, right: Option<BvhChild>, time0: f64, time1: f64) -> BVHNode {
         let bounding_box = match (
-            left.bounding_box(time0, time1),
-            right.bounding_box(time0, time1),
+            left.hittable().bounding_box(time0, time1),
+            right
+                .as_ref()
+                .map(|r| r.hittable().bounding_box(time0, time1)),
         ) {
-            (Some(l_box), Some(r_box)) => surrounding_box(&l_box, &r_box),
+            (Some(l_box), Some(Some(r_box))) => surrounding_box(&l_box, &r_box),
+            (Some(l_box), None) => l_box,
             _ => panic!("no bounding box found for either l or r"),
         };
 
         BVHNode {
-            left: left,
-            right: right,
+            left,
+            right,
             bounding_box,
         }
     }
@@ -378,8 +1659,11 @@ impl BVHNode {
 impl Hittable for BVHNode {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         if self.bounding_box.hit(r, t_min, t_max) {
-            let hit_left = self.left.hit(r, t_min, t_max);
-            let hit_right = self.right.hit(r, t_min, t_max);
+            let hit_left = self.left.hittable().hit(r, t_min, t_max);
+            let hit_right = self
+                .right
+                .as_ref()
+                .and_then(|right| right.hittable().hit(r, t_min, t_max));
             match (hit_left, hit_right) {
                 (Some(left_rec), Some(right_rec)) => {
                     if left_rec.t < right_rec.t {
@@ -393,7 +1677,7 @@ impl Hittable for BVHNode {
                 (None, None) => None,
             }
         } else {
-            return None;
+            None
         }
     }
 
@@ -401,18 +1685,34 @@ impl Hittable for BVHNode {
         Some(self.bounding_box)
     }
 
-    fn box_clone(&self) -> Box<Hittable> {
+    fn box_clone(&self) -> Box<dyn Hittable> {
         Box::new((*self).clone())
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use material::Lambertian;
+    use material::{DiffuseLight, Lambertian};
+
+    /// A mid grey diffuse material for test geometry.
+    pub(crate) fn grey() -> Arc<dyn Material> {
+        Arc::new(Lambertian {
+            albedo: vec3(0.5, 0.5, 0.5),
+        })
+    }
+
+    pub(crate) fn ball(center: Vec3, radius: f64) -> Box<dyn Hittable> {
+        Box::new(Sphere {
+            center,
+            radius,
+            material: grey(),
+        })
+    }
+
     #[test]
     fn bvh_node_with_one_item() {
-        let v: Vec<Box<Hittable>> = vec![Box::new(Sphere {
+        let v: Vec<Box<dyn Hittable>> = vec![Box::new(Sphere {
             center: vec3(0, 0, 0),
             radius: 1.0,
             material: Arc::new(Lambertian {
@@ -425,5 +1725,242 @@ mod tests {
         println!("{:?}", b);
 
         assert_eq!(b.max(), Vec3::new([1.0, 1.0, 1.0]));
+        let r = Ray::new(vec3(0, 0, 5), vec3(0, 0, -1), 0.0);
+        assert!((n.hit(&r, 0.001, f64::MAX).unwrap().t - 4.0).abs() < 1e-12);
+    }
+
+    fn test_triangle() -> Triangle {
+        Triangle::new(vec3(0, 0, 0), vec3(1, 0, 0), vec3(0, 1, 0), grey())
+    }
+
+    #[test]
+    fn triangle_hit_reports_barycentrics() {
+        let tri = test_triangle();
+        let r = Ray::new(vec3(0.25, 0.5, 1.0), vec3(0, 0, -1), 0.0);
+        let rec = tri.hit(&r, 0.001, f64::MAX).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-12);
+        assert!((rec.u - 0.25).abs() < 1e-12 && (rec.v - 0.5).abs() < 1e-12);
+        assert_eq!(rec.normal, vec3(0, 0, 1));
+
+        let miss = Ray::new(vec3(0.75, 0.5, 1.0), vec3(0, 0, -1), 0.0);
+        assert!(tri.hit(&miss, 0.001, f64::MAX).is_none());
+        let parallel = Ray::new(vec3(-1.0, 0.25, 0.0), vec3(1, 0, 0), 0.0);
+        assert!(tri.hit(&parallel, 0.001, f64::MAX).is_none());
+    }
+
+    #[test]
+    fn triangle_interpolates_vertex_data() {
+        let tri = test_triangle()
+            .with_normals([vec3(0, 0, 1), vec3(1, 0, 0), vec3(0, 0, 1)])
+            .with_uvs([(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)]);
+        let r = Ray::new(vec3(0.5, 0.0, 1.0), vec3(0, 0, -1), 0.0);
+        let rec = tri.hit(&r, 0.001, f64::MAX).unwrap();
+        assert!((rec.normal - vec3(1, 0, 1).unit_vector()).length() < 1e-12);
+        assert!((rec.u - 0.5).abs() < 1e-12 && rec.v.abs() < 1e-12);
+    }
+
+    #[test]
+    fn flat_triangle_box_has_volume() {
+        let b = test_triangle().bounding_box(0.0, 0.0).unwrap();
+        assert!(b.max().z() > b.min().z());
+        let r = Ray::new(vec3(0.2, 0.2, 1.0), vec3(0, 0, -1), 0.0);
+        assert!(b.hit(&r, 0.001, f64::MAX));
+    }
+
+    #[test]
+    fn rect_hit_and_padded_box() {
+        let rect = XZRect {
+            x0: 0.0,
+            x1: 2.0,
+            z0: 0.0,
+            z1: 1.0,
+            k: 3.0,
+            material: grey(),
+        };
+        let r = Ray::new(vec3(1.5, 0.0, 0.25), vec3(0, 1, 0), 0.0);
+        let rec = rect.hit(&r, 0.001, f64::MAX).unwrap();
+        assert_eq!(rec.t, 3.0);
+        assert_eq!(rec.normal, vec3(0, 1, 0));
+        assert_eq!((rec.u, rec.v), (0.75, 0.25));
+        let miss = Ray::new(vec3(2.5, 0.0, 0.25), vec3(0, 1, 0), 0.0);
+        assert!(rect.hit(&miss, 0.001, f64::MAX).is_none());
+
+        let b = rect.bounding_box(0.0, 0.0).unwrap();
+        assert!(b.min().y() < 3.0 && b.max().y() > 3.0);
+        assert!(b.hit(&r, 0.001, f64::MAX));
+    }
+
+    #[test]
+    fn rect_light_pdf_matches_sampling() {
+        // the solid angle of a small rect straight overhead is about area / d^2
+        let rect = XZRect {
+            x0: -0.05,
+            x1: 0.05,
+            z0: -0.05,
+            z1: 0.05,
+            k: 1.0,
+            material: Arc::new(DiffuseLight {
+                emit: vec3(1, 1, 1),
+            }),
+        };
+        let origin = vec3(0, 0, 0);
+        let pdf = rect.pdf_value(origin, vec3(0, 1, 0));
+        assert!((pdf - 100.0).abs() < 1e-9);
+        for _ in 0..10 {
+            let d = rect.random_direction(origin);
+            assert!(rect.pdf_value(origin, d) > 0.0);
+        }
+        assert_eq!(rect.pdf_value(origin, vec3(0, -1, 0)), 0.0);
+    }
+
+    #[test]
+    fn box_normals_point_outwards() {
+        let b = BoxShape::new(vec3(-1, -1, -1), vec3(1, 1, 1), grey());
+        let axes = [vec3(1, 0, 0), vec3(0, 1, 0), vec3(0, 0, 1)];
+        for &axis in &axes {
+            for &sign in &[1.0, -1.0] {
+                let outside = sign * 3.0 * axis;
+                let rec = b
+                    .hit(&Ray::new(outside, -outside, 0.0), 0.001, f64::MAX)
+                    .unwrap();
+                assert_eq!(rec.normal, sign * axis);
+                assert!((rec.t - 2.0 / 3.0).abs() < 1e-12);
+            }
+        }
+        assert_eq!(b.bounding_box(0.0, 0.0).unwrap().max(), vec3(1, 1, 1));
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn cylinder_sides_and_caps() {
+        let capped = Cylinder::new(vec3(0, 1, 0), 1.0, 2.0, true, grey());
+        let side = Ray::new(vec3(-5, 2, 0), vec3(1, 0, 0), 0.0);
+        let rec = capped.hit(&side, 0.001, f64::MAX).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert_close(rec.normal, vec3(-1, 0, 0));
+        assert!((rec.v - 0.5).abs() < 1e-9);
+
+        let top = Ray::new(vec3(0.5, 10.0, 0.0), vec3(0, -1, 0), 0.0);
+        let rec = capped.hit(&top, 0.001, f64::MAX).unwrap();
+        assert_close(rec.p, vec3(0.5, 3.0, 0.0));
+        assert_close(rec.normal, vec3(0, 1, 0));
+
+        // an open tube is seen through, and its inside faces the ray
+        let open = Cylinder::new(vec3(0, 1, 0), 1.0, 2.0, false, grey());
+        let rec = open.hit(&top, 0.001, f64::MAX);
+        assert!(rec.is_none());
+        let inside = Ray::new(vec3(0, 2, 0), vec3(1, 0, 0), 0.0);
+        assert_close(
+            open.hit(&inside, 0.001, f64::MAX).unwrap().normal,
+            vec3(-1, 0, 0),
+        );
+
+        let b = capped.bounding_box(0.0, 0.0).unwrap();
+        assert_close(b.min(), vec3(-1, 1, -1));
+        assert_close(b.max(), vec3(1, 3, 1));
+    }
+
+    #[test]
+    fn cone_slope_normal() {
+        // a 45 degree cone: the side normal is tilted up by 45 degrees
+        let cone = Cone::new(vec3(0, 0, 0), 1.0, 1.0, true, grey());
+        let r = Ray::new(vec3(-5.0, 0.5, 0.0), vec3(1, 0, 0), 0.0);
+        let rec = cone.hit(&r, 0.001, f64::MAX).unwrap();
+        assert_close(rec.p, vec3(-0.5, 0.5, 0.0));
+        assert_close(rec.normal, vec3(-1, 1, 0).unit_vector());
+
+        let below = Ray::new(vec3(0.2, -1.0, 0.0), vec3(0, 1, 0), 0.0);
+        assert_close(
+            cone.hit(&below, 0.001, f64::MAX).unwrap().normal,
+            vec3(0, -1, 0),
+        );
+        // above the apex the double cone must not be hit
+        let above = Ray::new(vec3(-5.0, 1.5, 0.0), vec3(1, 0, 0), 0.0);
+        assert!(cone.hit(&above, 0.001, f64::MAX).is_none());
+    }
+
+    #[test]
+    fn disk_and_annulus() {
+        let ring = Annulus::new(vec3(0, 0, 0), 0.5, 1.0, grey());
+        let disk = Disk::new(vec3(0, 0, 0), 1.0, grey());
+        let center = Ray::new(vec3(0, 1, 0), vec3(0, -1, 0), 0.0);
+        assert!(ring.hit(&center, 0.001, f64::MAX).is_none());
+        assert_close(
+            disk.hit(&center, 0.001, f64::MAX).unwrap().normal,
+            vec3(0, 1, 0),
+        );
+
+        let from_below = Ray::new(vec3(0.75, -1.0, 0.0), vec3(0, 1, 0), 0.0);
+        let rec = ring.hit(&from_below, 0.001, f64::MAX).unwrap();
+        assert_close(rec.normal, vec3(0, -1, 0));
+        assert!((rec.v - 0.5).abs() < 1e-9);
+        let b = ring.bounding_box(0.0, 0.0).unwrap();
+        assert!(b.min().y() < 0.0 && b.max().y() > 0.0);
+    }
+
+    #[test]
+    fn paraboloid_dish() {
+        let dish = Paraboloid::new(vec3(0, 0, 0), 2.0, 1.0, grey());
+        // y = x^2 / 4, so at x = 1 the dish is at y = 0.25 with slope 0.5
+        let r = Ray::new(vec3(1, 5, 0), vec3(0, -1, 0), 0.0);
+        let rec = dish.hit(&r, 0.001, f64::MAX).unwrap();
+        assert_close(rec.p, vec3(1.0, 0.25, 0.0));
+        assert_close(rec.normal, vec3(-0.5, 1.0, 0.0).unit_vector());
+
+        let outside = Ray::new(vec3(3, 5, 0), vec3(0, -1, 0), 0.0);
+        assert!(dish.hit(&outside, 0.001, f64::MAX).is_none());
+        assert_close(dish.bounding_box(0.0, 0.0).unwrap().max(), vec3(2, 1, 2));
+    }
+
+    #[test]
+    fn sah_tree_matches_a_plain_list() {
+        let mut rng = thread_rng();
+        let mut random = || rng.gen::<f64>() * 20.0 - 10.0;
+        let spheres: Vec<Box<dyn Hittable>> = (0..200)
+            .map(|_| ball(vec3(random(), random(), random()), 0.5))
+            .collect();
+        let list = HittableList {
+            list: spheres.clone(),
+        };
+        let sah = BVHNode::with_builder(spheres.clone(), 0.0, 1.0, BvhBuilder::default());
+        let median = BVHNode::with_builder(spheres, 0.0, 1.0, BvhBuilder::Median);
+
+        for _ in 0..500 {
+            let r = Ray::new(
+                vec3(random(), random(), random()),
+                vec3(random(), random(), random()),
+                0.0,
+            );
+            let expected = list.hit(&r, 0.001, f64::MAX).map(|rec| rec.t);
+            assert_eq!(sah.hit(&r, 0.001, f64::MAX).map(|rec| rec.t), expected);
+            assert_eq!(median.hit(&r, 0.001, f64::MAX).map(|rec| rec.t), expected);
+        }
+    }
+
+    #[test]
+    fn sah_keeps_overlapping_objects_in_a_leaf() {
+        let cluster = |x: f64| {
+            (0..3)
+                .map(move |i| ball(vec3(x + 0.01 * i as f64, 0.0, 0.0), 1.0))
+                .collect::<Vec<_>>()
+        };
+        let mut objects = cluster(0.0);
+        objects.extend(cluster(100.0));
+
+        // each cluster is a single leaf under the root
+        let tree = BVHNode::with_builder(objects.clone(), 0.0, 1.0, BvhBuilder::default());
+        assert_eq!(tree.depth(), 1);
+        assert_eq!(tree.leaf_count(), 2);
+
+        let no_leaves = BvhBuilder::Sah {
+            bins: 12,
+            max_leaf_size: 1,
+        };
+        let tree = BVHNode::with_builder(objects, 0.0, 1.0, no_leaves);
+        assert_eq!(tree.leaf_count(), 6);
+        assert!(tree.depth() >= 3);
     }
 }
@@ -0,0 +1,332 @@
+//!
+//! Reading and writing Radiance RGBE (.hdr) images
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use vec3::{vec3, Vec3};
+
+/// A linear RGB image stored row by row, top row first.
+#[derive(Clone, Debug)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+#[derive(Debug)]
+pub enum HdrError {
+    Io(io::Error),
+    Format(String),
+}
+
+impl fmt::Display for HdrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HdrError::Io(ref e) => write!(f, "{}", e),
+            HdrError::Format(ref msg) => write!(f, "invalid hdr image: {}", msg),
+        }
+    }
+}
+
+impl error::Error for HdrError {}
+
+impl From<io::Error> for HdrError {
+    fn from(e: io::Error) -> HdrError {
+        HdrError::Io(e)
+    }
+}
+
+fn format_error<T, S: Into<String>>(msg: S) -> Result<T, HdrError> {
+    Err(HdrError::Format(msg.into()))
+}
+
+pub fn rgbe_to_rgb(rgbe: [u8; 4]) -> Vec3 {
+    if rgbe[3] == 0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    let f = 2f64.powi(i32::from(rgbe[3]) - (128 + 8));
+    vec3(
+        (f64::from(rgbe[0]) + 0.5) * f,
+        (f64::from(rgbe[1]) + 0.5) * f,
+        (f64::from(rgbe[2]) + 0.5) * f,
+    )
+}
+
+/// Shares one exponent between the three channels, as in Greg Ward's
+/// `float2rgbe`.
+pub fn rgb_to_rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let v = rgb[0].max(rgb[1]).max(rgb[2]);
+    if v.is_nan() || v < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    if v / 2f32.powi(e) >= 1.0 {
+        e += 1;
+    } else if v / 2f32.powi(e) < 0.5 {
+        e -= 1;
+    }
+    let scale = 256.0 / 2f32.powi(e);
+    let channel = |c: f32| (c.max(0.0) * scale).min(255.0) as u8;
+    [
+        channel(rgb[0]),
+        channel(rgb[1]),
+        channel(rgb[2]),
+        (e + 128).clamp(0, 255) as u8,
+    ]
+}
+
+/// Writes run length encoded scanlines, or flat ones for widths the run
+/// length scheme can't describe.
+pub fn encode_hdr<W: Write>(
+    w: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[[f32; 3]],
+) -> io::Result<()> {
+    write!(
+        w,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+
+    let mut channel = vec![0u8; width];
+    for row in pixels.chunks(width).take(height) {
+        let rgbe: Vec<[u8; 4]> = row.iter().map(|p| rgb_to_rgbe(*p)).collect();
+        if !(8..0x8000).contains(&width) {
+            for px in &rgbe {
+                w.write_all(px)?;
+            }
+            continue;
+        }
+
+        w.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        for c in 0..4 {
+            for (dst, px) in channel.iter_mut().zip(&rgbe) {
+                *dst = px[c];
+            }
+            write_rle_channel(w, &channel)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_rle_channel<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+    const MIN_RUN: usize = 4;
+    let mut cur = 0;
+    while cur < data.len() {
+        // find the next run worth encoding
+        let mut run_start = cur;
+        let mut run_len = 0;
+        while run_start < data.len() {
+            run_len = 1;
+            while run_start + run_len < data.len()
+                && run_len < 127
+                && data[run_start + run_len] == data[run_start]
+            {
+                run_len += 1;
+            }
+            if run_len >= MIN_RUN {
+                break;
+            }
+            run_start += run_len;
+        }
+
+        // literal bytes up to the run
+        while cur < run_start {
+            let count = (run_start - cur).min(128);
+            w.write_all(&[count as u8])?;
+            w.write_all(&data[cur..cur + count])?;
+            cur += count;
+        }
+
+        if run_len >= MIN_RUN {
+            w.write_all(&[128 + run_len as u8, data[run_start]])?;
+            cur += run_len;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_hdr<P: AsRef<Path>>(path: P) -> Result<HdrImage, HdrError> {
+    let file = File::open(path)?;
+    decode_hdr(&mut BufReader::new(file))
+}
+
+fn read_line<R: BufRead>(r: &mut R) -> Result<String, HdrError> {
+    let mut buf = vec![];
+    r.read_until(b'\n', &mut buf)?;
+    if buf.is_empty() {
+        return format_error("unexpected end of header");
+    }
+    Ok(String::from_utf8_lossy(&buf).trim_end().to_string())
+}
+
+/// Larger images are rejected rather than risk a huge allocation.
+const MAX_PIXELS: usize = 1 << 28;
+
+pub fn decode_hdr<R: BufRead>(r: &mut R) -> Result<HdrImage, HdrError> {
+    let magic = read_line(r)?;
+    if !magic.starts_with("#?") {
+        return format_error("missing #? signature");
+    }
+
+    loop {
+        let line = read_line(r)?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return format_error(format!("unsupported {}", line));
+        }
+    }
+
+    let resolution = read_line(r)?;
+    let (height, width): (usize, usize) = match resolution.split_whitespace().collect::<Vec<_>>()[..]
+    {
+        ["-Y", h, "+X", w] => match (h.parse(), w.parse()) {
+            (Ok(h), Ok(w)) => (h, w),
+            _ => return format_error(format!("bad resolution '{}'", resolution)),
+        },
+        _ => return format_error(format!("unsupported orientation '{}'", resolution)),
+    };
+
+    // the header is untrusted, so check it before sizing buffers from it
+    match width.checked_mul(height) {
+        Some(n) if n > 0 && n <= MAX_PIXELS => {}
+        _ => return format_error(format!("bad resolution '{}'", resolution)),
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        read_scanline(r, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|p| rgbe_to_rgb(*p)));
+    }
+
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn read_scanline<R: Read>(r: &mut R, scanline: &mut [[u8; 4]]) -> Result<(), HdrError> {
+    let width = scanline.len();
+    let mut head = [0u8; 4];
+    r.read_exact(&mut head)?;
+
+    let is_rle =
+        (8..0x8000).contains(&width) && head[0] == 2 && head[1] == 2 && head[2] & 0x80 == 0;
+    if !is_rle {
+        // flat pixels, the first of which we have already read
+        scanline[0] = head;
+        for px in scanline.iter_mut().skip(1) {
+            r.read_exact(px)?;
+        }
+        return Ok(());
+    }
+
+    if (usize::from(head[2]) << 8 | usize::from(head[3])) != width {
+        return format_error("scanline width mismatch");
+    }
+
+    // each of the four channels is run length encoded separately
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            r.read_exact(&mut count)?;
+            let count = usize::from(count[0]);
+            if count > 128 {
+                let run = count - 128;
+                if x + run > width {
+                    return format_error("run overflows scanline");
+                }
+                let mut value = [0u8; 1];
+                r.read_exact(&mut value)?;
+                for px in &mut scanline[x..x + run] {
+                    px[channel] = value[0];
+                }
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return format_error("bad literal run");
+                }
+                let mut values = vec![0u8; count];
+                r.read_exact(&mut values)?;
+                for (px, v) in scanline[x..x + count].iter_mut().zip(values) {
+                    px[channel] = v;
+                }
+                x += count;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_flat_and_rle_scanlines() {
+        let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        // first row flat: one bright pixel then black
+        data.extend_from_slice(&[128, 64, 32, 129]);
+        data.extend_from_slice(&[0u8; 28]);
+        // second row rle: every channel is a run of 8
+        data.extend_from_slice(&[2, 2, 0, 8]);
+        for v in &[128u8, 128, 128, 128] {
+            data.extend_from_slice(&[128 + 8, *v]);
+        }
+
+        let img = decode_hdr(&mut &data[..]).unwrap();
+        assert_eq!((img.width, img.height), (8, 2));
+        assert!((img.pixels[0].x() - 1.00390625).abs() < 1e-9);
+        assert_eq!(img.pixels[1], vec3(0, 0, 0));
+        assert!((img.pixels[15].z() - 0.501953125).abs() < 1e-9);
+    }
+
+    #[test]
+    fn round_trips_through_rle() {
+        let width = 20;
+        let pixels: Vec<[f32; 3]> = (0..width * 3)
+            .map(|i| {
+                let v = if i % 7 < 4 { 0.25 } else { i as f32 * 3.5 };
+                [v, v * 0.5, 0.0]
+            })
+            .collect();
+        let mut data = vec![];
+        encode_hdr(&mut data, width, 3, &pixels).unwrap();
+
+        let img = decode_hdr(&mut &data[..]).unwrap();
+        assert_eq!((img.width, img.height), (width, 3));
+        for (a, b) in pixels.iter().zip(&img.pixels) {
+            // rgbe keeps about 8 bits of mantissa
+            assert!((f64::from(a[0]) - b.x()).abs() <= f64::from(a[0]) / 128.0 + 1e-6);
+            assert!((f64::from(a[1]) - b.y()).abs() <= f64::from(a[0]) / 128.0 + 1e-6);
+        }
+    }
+
+    #[test]
+    fn rejects_missing_signature() {
+        assert!(decode_hdr(&mut &b"P6\n"[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_and_huge_resolutions() {
+        for resolution in &["-Y 1 +X 0", "-Y 0 +X 4", "-Y 99999999 +X 99999999"] {
+            let data = format!("#?RADIANCE\n\n{}\n", resolution);
+            match decode_hdr(&mut data.as_bytes()) {
+                Err(HdrError::Format(_)) => {}
+                other => panic!("{}: expected a format error, got {:?}", resolution, other),
+            }
+        }
+    }
+}
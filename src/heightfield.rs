@@ -0,0 +1,477 @@
+//!
+//! Terrain from a grid of height samples.
+//!
+//! Every grid cell is two triangles, but they are never stored: rays walk an
+//! implicit quadtree of per-node min/max heights and only the cells whose
+//! bounds they pass through are intersected.
+use bvh::AABB;
+use geo::{intersect_triangle, HitRecord, Hittable};
+use material::Material;
+use png;
+use png::HasParameters;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use vec3::{vec3, Ray, Vec3};
+
+#[derive(Debug)]
+pub enum HeightFieldError {
+    Io(io::Error),
+    Png(png::DecodingError),
+    Format(String),
+}
+
+impl fmt::Display for HeightFieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeightFieldError::Io(ref e) => write!(f, "{}", e),
+            HeightFieldError::Png(ref e) => write!(f, "{}", e),
+            HeightFieldError::Format(ref msg) => write!(f, "invalid height map: {}", msg),
+        }
+    }
+}
+
+impl error::Error for HeightFieldError {}
+
+impl From<io::Error> for HeightFieldError {
+    fn from(e: io::Error) -> HeightFieldError {
+        HeightFieldError::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for HeightFieldError {
+    fn from(e: png::DecodingError) -> HeightFieldError {
+        HeightFieldError::Png(e)
+    }
+}
+
+/// Reads an 8 or 16 bit PNG as heights in `[0, 1]`, row by row. Colour
+/// images are averaged over their colour channels.
+pub fn decode_height_png<R: Read>(r: R) -> Result<(usize, usize, Vec<f32>), HeightFieldError> {
+    let mut decoder = png::Decoder::new(r);
+    // keep 16 bit samples instead of stripping them to 8
+    decoder.set(png::Transformations::IDENTITY);
+    let (info, mut reader) = decoder.read_info()?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 1,
+        png::ColorType::RGB | png::ColorType::RGBA => 3,
+        png::ColorType::Indexed => {
+            return Err(HeightFieldError::Format(
+                "indexed images are not supported".into(),
+            ))
+        }
+    };
+    let stride = info.color_type.samples();
+    let (bytes, max) = match info.bit_depth {
+        png::BitDepth::Eight => (1, 255.0),
+        png::BitDepth::Sixteen => (2, 65535.0),
+        depth => {
+            return Err(HeightFieldError::Format(format!(
+                "unsupported bit depth {}",
+                depth as u8
+            )))
+        }
+    };
+
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let sample = |offset: usize| {
+        if bytes == 1 {
+            f32::from(buf[offset])
+        } else {
+            f32::from(u16::from(buf[offset]) << 8 | u16::from(buf[offset + 1]))
+        }
+    };
+    let mut heights = Vec::with_capacity(width * height);
+    for row in 0..height {
+        for col in 0..width {
+            let pixel = row * info.line_size + col * stride * bytes;
+            let sum: f32 = (0..channels).map(|c| sample(pixel + c * bytes)).sum();
+            heights.push(sum / (channels as f32 * max));
+        }
+    }
+    Ok((width, height, heights))
+}
+
+/// Min/max heights of the nodes of one quadtree level.
+#[derive(Clone, Debug)]
+struct Level {
+    width: usize,
+    depth: usize,
+    ranges: Vec<(f32, f32)>,
+}
+
+/// A terrain over the rectangle from `min` to `min + size` in x and z. Sample
+/// `(i, j)` sits at the `i`th column in x and `j`th row in z, raised by its
+/// height times `size.y()` above `min.y()`.
+#[derive(Clone, Debug)]
+pub struct HeightField {
+    pub width: usize,
+    pub depth: usize,
+    pub min: Vec3,
+    pub size: Vec3,
+    pub material: Arc<dyn Material>,
+    heights: Arc<Vec<f32>>,
+    /// Level 0 holds one node per cell; the last level is the root.
+    levels: Arc<Vec<Level>>,
+}
+
+impl HeightField {
+    /// `heights` are row-major with `width` samples per row, in `[0, 1]`.
+    pub fn new(
+        width: usize,
+        depth: usize,
+        heights: Vec<f32>,
+        min: Vec3,
+        size: Vec3,
+        material: Arc<dyn Material>,
+    ) -> HeightField {
+        assert!(width >= 2 && depth >= 2, "a height field needs 2x2 samples");
+        assert_eq!(heights.len(), width * depth);
+
+        let cells = Level {
+            width: width - 1,
+            depth: depth - 1,
+            ranges: (0..depth - 1)
+                .flat_map(|j| (0..width - 1).map(move |i| (i, j)))
+                .map(|(i, j)| {
+                    let corners = [
+                        heights[j * width + i],
+                        heights[j * width + i + 1],
+                        heights[(j + 1) * width + i],
+                        heights[(j + 1) * width + i + 1],
+                    ];
+                    let lo = corners.iter().cloned().fold(f32::MAX, f32::min);
+                    let hi = corners.iter().cloned().fold(f32::MIN, f32::max);
+                    (lo, hi)
+                })
+                .collect(),
+        };
+
+        let mut levels = vec![cells];
+        loop {
+            let below = &levels[levels.len() - 1];
+            if below.width == 1 && below.depth == 1 {
+                break;
+            }
+            let (w, d) = (below.width.div_ceil(2), below.depth.div_ceil(2));
+            let mut ranges = Vec::with_capacity(w * d);
+            for j in 0..d {
+                for i in 0..w {
+                    let mut range = (f32::MAX, f32::MIN);
+                    for (ci, cj) in HeightField::children(below, i, j) {
+                        let (lo, hi) = below.ranges[cj * below.width + ci];
+                        range = (range.0.min(lo), range.1.max(hi));
+                    }
+                    ranges.push(range);
+                }
+            }
+            levels.push(Level {
+                width: w,
+                depth: d,
+                ranges,
+            });
+        }
+
+        HeightField {
+            width,
+            depth,
+            min,
+            size,
+            material,
+            heights: Arc::new(heights),
+            levels: Arc::new(levels),
+        }
+    }
+
+    /// Loads a grayscale PNG, one sample per pixel.
+    pub fn load_png<P: AsRef<Path>>(
+        path: P,
+        min: Vec3,
+        size: Vec3,
+        material: Arc<dyn Material>,
+    ) -> Result<HeightField, HeightFieldError> {
+        let (width, depth, heights) = decode_height_png(File::open(path)?)?;
+        if width < 2 || depth < 2 {
+            return Err(HeightFieldError::Format(
+                "height maps need at least 2x2 pixels".into(),
+            ));
+        }
+        Ok(HeightField::new(width, depth, heights, min, size, material))
+    }
+
+    /// The nodes of the level below `level` covered by node `(i, j)`.
+    fn children(level: &Level, i: usize, j: usize) -> Vec<(usize, usize)> {
+        let mut children = vec![];
+        for cj in 2 * j..(2 * j + 2).min(level.depth) {
+            for ci in 2 * i..(2 * i + 2).min(level.width) {
+                children.push((ci, cj));
+            }
+        }
+        children
+    }
+
+    fn height(&self, i: usize, j: usize) -> f64 {
+        f64::from(self.heights[j * self.width + i])
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (
+            self.size.x() / (self.width - 1) as f64,
+            self.size.z() / (self.depth - 1) as f64,
+        )
+    }
+
+    fn point(&self, i: usize, j: usize) -> Vec3 {
+        let (dx, dz) = self.cell_size();
+        self.min
+            + vec3(
+                i as f64 * dx,
+                self.height(i, j) * self.size.y(),
+                j as f64 * dz,
+            )
+    }
+
+    /// Smooth normal at a sample, by central differences of the heights.
+    fn vertex_normal(&self, i: usize, j: usize) -> Vec3 {
+        let (dx, dz) = self.cell_size();
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.width - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.depth - 1));
+        let slope_x =
+            (self.height(i1, j) - self.height(i0, j)) * self.size.y() / ((i1 - i0) as f64 * dx);
+        let slope_z =
+            (self.height(i, j1) - self.height(i, j0)) * self.size.y() / ((j1 - j0) as f64 * dz);
+        vec3(-slope_x, 1.0, -slope_z).unit_vector()
+    }
+
+    /// Bounds of node `(i, j)` of `level`, padded so flat terrain still has
+    /// some thickness.
+    fn node_box(&self, level: usize, i: usize, j: usize) -> AABB {
+        let cells = &self.levels[0];
+        let (lo, hi) = self.levels[level].ranges[j * self.levels[level].width + i];
+        let (dx, dz) = self.cell_size();
+        let (i0, i1) = (i << level, ((i + 1) << level).min(cells.width));
+        let (j0, j1) = (j << level, ((j + 1) << level).min(cells.depth));
+        let pad = 1e-6 * (1.0 + self.size.y().abs());
+        AABB {
+            min: self.min
+                + vec3(
+                    i0 as f64 * dx,
+                    f64::from(lo) * self.size.y() - pad,
+                    j0 as f64 * dz,
+                ),
+            max: self.min
+                + vec3(
+                    i1 as f64 * dx,
+                    f64::from(hi) * self.size.y() + pad,
+                    j1 as f64 * dz,
+                ),
+        }
+    }
+
+    fn hit_cell(&self, r: &Ray, i: usize, j: usize, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // both triangles are wound so their normals point up
+        let corners = [(i, j), (i + 1, j + 1), (i + 1, j), (i, j + 1)];
+        let triangles = [[0, 1, 2], [0, 3, 1]];
+        let mut best: Option<HitRecord> = None;
+        for tri in &triangles {
+            let [a, b, c] = [corners[tri[0]], corners[tri[1]], corners[tri[2]]];
+            let t_max = best.as_ref().map_or(t_max, |rec| rec.t);
+            let (t, b1, b2) = match intersect_triangle(
+                r,
+                self.point(a.0, a.1),
+                self.point(b.0, b.1),
+                self.point(c.0, c.1),
+                t_min,
+                t_max,
+            ) {
+                Some(hit) => hit,
+                None => continue,
+            };
+            let b0 = 1.0 - b1 - b2;
+            let normal = (b0 * self.vertex_normal(a.0, a.1)
+                + b1 * self.vertex_normal(b.0, b.1)
+                + b2 * self.vertex_normal(c.0, c.1))
+            .unit_vector();
+            let p = r.point_at_parameter(t);
+            best = Some(HitRecord {
+                t,
+                p,
+                normal,
+                u: (p.x() - self.min.x()) / self.size.x(),
+                v: (p.z() - self.min.z()) / self.size.z(),
+                material: Arc::clone(&self.material),
+                tangent: None,
+                color: None,
+            });
+        }
+        best
+    }
+
+    fn hit_node(
+        &self,
+        r: &Ray,
+        level: usize,
+        i: usize,
+        j: usize,
+        t_min: f64,
+        mut t_max: f64,
+    ) -> Option<HitRecord> {
+        if level == 0 {
+            return self.hit_cell(r, i, j, t_min, t_max);
+        }
+
+        // visit the children front to back, skipping any that start beyond
+        // the closest hit so far
+        let below = &self.levels[level - 1];
+        let mut children: Vec<(f64, usize, usize)> = HeightField::children(below, i, j)
+            .into_iter()
+            .filter_map(|(ci, cj)| {
+                self.node_box(level - 1, ci, cj)
+                    .hit_interval(r, t_min, t_max)
+                    .map(|(enter, _)| (enter, ci, cj))
+            })
+            .collect();
+        children.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut best = None;
+        for (enter, ci, cj) in children {
+            if enter >= t_max {
+                break;
+            }
+            if let Some(rec) = self.hit_node(r, level - 1, ci, cj, t_min, t_max) {
+                t_max = rec.t;
+                best = Some(rec);
+            }
+        }
+        best
+    }
+}
+
+impl Hittable for HeightField {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let root = self.levels.len() - 1;
+        self.node_box(root, 0, 0).hit_interval(r, t_min, t_max)?;
+        self.hit_node(r, root, 0, 0, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.node_box(self.levels.len() - 1, 0, 0))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::tests::grey;
+    use rand::prelude::*;
+    use std::f64;
+    use std::fs;
+
+    fn field(width: usize, depth: usize, heights: Vec<f32>) -> HeightField {
+        HeightField::new(
+            width,
+            depth,
+            heights,
+            vec3(0, 0, 0),
+            vec3(10, 2, 10),
+            grey(),
+        )
+    }
+
+    #[test]
+    fn ramp_hit_point_and_normal() {
+        // rises along x from 0 to 2 over 10 units
+        let (w, d) = (5, 3);
+        let heights = (0..w * d)
+            .map(|k| (k % w) as f32 / (w - 1) as f32)
+            .collect();
+        let ramp = field(w, d, heights);
+
+        let r = Ray::new(vec3(5, 10, 5), vec3(0, -1, 0), 0.0);
+        let rec = ramp.hit(&r, 0.001, f64::MAX).unwrap();
+        assert!((rec.p.y() - 1.0).abs() < 1e-9);
+        assert!((rec.normal - vec3(-0.2, 1.0, 0.0).unit_vector()).length() < 1e-9);
+        assert!((rec.u - 0.5).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9);
+
+        let outside = Ray::new(vec3(11, 10, 5), vec3(0, -1, 0), 0.0);
+        assert!(ramp.hit(&outside, 0.001, f64::MAX).is_none());
+        let b = ramp.bounding_box(0.0, 0.0).unwrap();
+        assert!(b.max().y() >= 2.0 && b.max().x() == 10.0);
+    }
+
+    #[test]
+    fn hierarchy_matches_brute_force() {
+        let mut rng = thread_rng();
+        let (w, d) = (13, 9);
+        let heights = (0..w * d).map(|_| rng.gen::<f64>() as f32).collect();
+        let terrain = field(w, d, heights);
+
+        for _ in 0..500 {
+            let origin = vec3(
+                rng.gen::<f64>() * 14.0 - 2.0,
+                3.0,
+                rng.gen::<f64>() * 14.0 - 2.0,
+            );
+            let direction = vec3(
+                rng.gen::<f64>() - 0.5,
+                -rng.gen::<f64>(),
+                rng.gen::<f64>() - 0.5,
+            );
+            let r = Ray::new(origin, direction, 0.0);
+
+            let mut brute: Option<f64> = None;
+            for j in 0..d - 1 {
+                for i in 0..w - 1 {
+                    if let Some(rec) = terrain.hit_cell(&r, i, j, 0.001, f64::MAX) {
+                        brute = Some(brute.map_or(rec.t, |t| t.min(rec.t)));
+                    }
+                }
+            }
+            let fast = terrain.hit(&r, 0.001, f64::MAX).map(|rec| rec.t);
+            assert_eq!(fast, brute);
+        }
+    }
+
+    #[test]
+    fn loads_sixteen_bit_png() {
+        let path = ::std::env::temp_dir().join("heightfield_test.png");
+        {
+            let file = fs::File::create(&path).unwrap();
+            let mut encoder = png::Encoder::new(file, 3, 2);
+            encoder
+                .set(png::ColorType::Grayscale)
+                .set(png::BitDepth::Sixteen);
+            let mut writer = encoder.write_header().unwrap();
+            let samples: [u16; 6] = [0, 32768, 65535, 1, 2, 3];
+            let bytes: Vec<u8> = samples
+                .iter()
+                .flat_map(|s| vec![(s >> 8) as u8, *s as u8])
+                .collect();
+            writer.write_image_data(&bytes).unwrap();
+        }
+
+        let (w, h, heights) = decode_height_png(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!((w, h), (3, 2));
+        assert_eq!(heights[2], 1.0);
+        assert!((heights[1] - 32768.0 / 65535.0).abs() < 1e-6);
+        assert!((heights[3] - 1.0 / 65535.0).abs() < 1e-9);
+
+        let material = grey();
+        let terrain = HeightField::load_png(&path, vec3(0, 0, 0), vec3(2, 1, 1), material).unwrap();
+        assert_eq!(terrain.width, 3);
+        fs::remove_file(&path).unwrap();
+    }
+}
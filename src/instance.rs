@@ -0,0 +1,314 @@
+//!
+//! Wrappers that place geometry in the world through an affine transform
+use bvh::{LinearBvh, AABB};
+use geo::{BvhBuilder, HitRecord, Hittable};
+use material::Material;
+use std::sync::Arc;
+use vec3::{Mat4, Ray, Transform, Vec3};
+
+/// World-space bounds of a box after transforming all eight corners.
+pub fn transform_box(transform: &Transform, b: &AABB) -> AABB {
+    matrix_box(&transform.matrix, b)
+}
+
+/// The box around `b` mapped by `matrix`, which need not be invertible.
+pub fn matrix_box(matrix: &Mat4, b: &AABB) -> AABB {
+    let mut corners = vec![];
+    for i in 0..8 {
+        let pick = |bit: usize, axis: usize| {
+            if i & bit == 0 {
+                b.min()[axis]
+            } else {
+                b.max()[axis]
+            }
+        };
+        corners.push(matrix.transform_point(Vec3::new([pick(1, 0), pick(2, 1), pick(4, 2)])));
+    }
+    let mut min = corners[0];
+    let mut max = corners[0];
+    for c in &corners[1..] {
+        min = min.apply_per_element(*c, f64::min);
+        max = max.apply_per_element(*c, f64::max);
+    }
+    AABB { min, max }
+}
+
+/// Intersects `inner` with the ray taken into object space, and brings the
+/// hit back out into world space.
+pub fn hit_transformed(
+    inner: &dyn Hittable,
+    transform: &Transform,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord> {
+    // the direction isn't normalised, so t is the same in both spaces
+    let local = Ray::new(
+        transform.inverse.transform_point(r.origin()),
+        transform.inverse.transform_vector(r.direction()),
+        r.time(),
+    );
+    inner.hit(&local, t_min, t_max).map(|rec| HitRecord {
+        p: r.point_at_parameter(rec.t),
+        normal: transform.normal(rec.normal).unit_vector(),
+        tangent: rec.tangent.map(|t| transform.vector(t).unit_vector()),
+        ..rec
+    })
+}
+
+/// The solid angle density of `inner` seen through `transform`. Directions
+/// are mapped into object space and the density is corrected by the Jacobian
+/// of that mapping, which is 1 for rigid motions and uniform scales.
+pub fn pdf_value_transformed(
+    inner: &dyn Hittable,
+    transform: &Transform,
+    origin: Vec3,
+    direction: Vec3,
+) -> f64 {
+    let w = direction.unit_vector();
+    let local = transform.inverse.transform_vector(w);
+    let pdf = inner.pdf_value(transform.inverse.transform_point(origin), local);
+    if pdf == 0.0 {
+        return 0.0;
+    }
+    let len = local.length();
+    pdf * transform.inverse.determinant3().abs() / (len * len * len)
+}
+
+/// An object placed in the world through a transform.
+#[derive(Clone, Debug)]
+pub struct Transformed {
+    pub inner: Box<dyn Hittable>,
+    pub transform: Transform,
+}
+
+impl Transformed {
+    pub fn new(inner: Box<dyn Hittable>, transform: Transform) -> Transformed {
+        Transformed { inner, transform }
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        hit_transformed(&*self.inner, &self.transform, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.inner
+            .bounding_box(t0, t1)
+            .map(|b| transform_box(&self.transform, &b))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        pdf_value_transformed(&*self.inner, &self.transform, origin, direction)
+    }
+
+    fn random_direction(&self, origin: Vec3) -> Vec3 {
+        let local = self
+            .inner
+            .random_direction(self.transform.inverse.transform_point(origin));
+        self.transform.vector(local)
+    }
+
+    fn is_sampleable(&self) -> bool {
+        self.inner.is_sampleable()
+    }
+}
+
+/// Geometry built once and shared by any number of `Instance`s, with its own
+/// BVH when it has more than one object.
+pub fn prototype(mut objects: Vec<Box<dyn Hittable>>, time0: f64, time1: f64) -> Arc<dyn Hittable> {
+    match objects.len() {
+        1 => Arc::from(objects.pop().unwrap()),
+        _ => Arc::new(LinearBvh::new(objects, time0, time1, BvhBuilder::default())),
+    }
+}
+
+/// A placement of a shared prototype. Only the transform and the optional
+/// material override belong to the instance, so cloning one never copies the
+/// prototype's geometry.
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub prototype: Arc<dyn Hittable>,
+    pub transform: Transform,
+    /// Replaces the prototype's materials when set.
+    pub material: Option<Arc<dyn Material>>,
+}
+
+impl Instance {
+    pub fn new(prototype: &Arc<dyn Hittable>, transform: Transform) -> Instance {
+        Instance {
+            prototype: Arc::clone(prototype),
+            transform,
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: Arc<dyn Material>) -> Instance {
+        Instance {
+            material: Some(material),
+            ..self
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rec = hit_transformed(&*self.prototype, &self.transform, r, t_min, t_max)?;
+        Some(match self.material {
+            Some(ref material) => HitRecord {
+                material: Arc::clone(material),
+                ..rec
+            },
+            None => rec,
+        })
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.prototype
+            .bounding_box(t0, t1)
+            .map(|b| transform_box(&self.transform, &b))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        pdf_value_transformed(&*self.prototype, &self.transform, origin, direction)
+    }
+
+    fn random_direction(&self, origin: Vec3) -> Vec3 {
+        let local = self
+            .prototype
+            .random_direction(self.transform.inverse.transform_point(origin));
+        self.transform.vector(local)
+    }
+
+    fn is_sampleable(&self) -> bool {
+        self.prototype.is_sampleable()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::tests::grey;
+    use geo::{BVHNode, Sphere, XYRect};
+    use material::DiffuseLight;
+    use std::f64;
+    use vec3::vec3;
+
+    #[test]
+    fn scaled_sphere_is_an_ellipsoid() {
+        let ellipsoid = Transformed::new(
+            Box::new(Sphere::new(vec3(0, 0, 0), 1.0, grey())),
+            Transform::scale(vec3(2, 1, 1)).then(&Transform::translate(vec3(0, 0, -5))),
+        );
+        let r = Ray::new(vec3(-10.0, 0.0, -5.0), vec3(1, 0, 0), 0.0);
+        let rec = ellipsoid.hit(&r, 0.001, f64::MAX).unwrap();
+        assert!((rec.t - 8.0).abs() < 1e-9);
+        assert!((rec.p - vec3(-2, 0, -5)).length() < 1e-9);
+        assert!((rec.normal - vec3(-1, 0, 0)).length() < 1e-9);
+
+        // off axis the normal follows the inverse transpose
+        let p = vec3(2.0 * 0.6, 0.8, -5.0);
+        let r = Ray::new(p + vec3(0, 5, 0), vec3(0, -1, 0), 0.0);
+        let rec = ellipsoid.hit(&r, 0.001, f64::MAX).unwrap();
+        assert!((rec.p - p).length() < 1e-9);
+        assert!((rec.normal - vec3(0.3, 0.8, 0.0).unit_vector()).length() < 1e-9);
+
+        let b = ellipsoid.bounding_box(0.0, 0.0).unwrap();
+        assert!((b.min() - vec3(-2, -1, -6)).length() < 1e-9);
+        assert!((b.max() - vec3(2, 1, -4)).length() < 1e-9);
+    }
+
+    #[test]
+    fn rotated_box_bounds_contain_corners() {
+        let rect = Transformed::new(
+            Box::new(XYRect {
+                x0: 0.0,
+                x1: 1.0,
+                y0: 0.0,
+                y1: 1.0,
+                k: 0.0,
+                material: grey(),
+            }),
+            Transform::rotate(vec3(0, 0, 1), 45.0),
+        );
+        let b = rect.bounding_box(0.0, 0.0).unwrap();
+        let h = 0.5f64.sqrt();
+        assert!((b.min().x() + h).abs() < 1e-9 && (b.max().x() - h).abs() < 1e-9);
+        assert!((b.max().y() - 2.0 * h).abs() < 1e-9);
+    }
+
+    #[test]
+    fn transformed_light_pdf_matches_solid_angle() {
+        // a unit sphere scaled up by 2 at distance 10 should look like a
+        // sphere of radius 2 there
+        let origin = vec3(0, 0, 0);
+        let big = Sphere::new(vec3(0, 0, -10), 2.0, grey());
+        let scaled = Transformed::new(
+            Box::new(Sphere::new(vec3(0, 0, -5), 1.0, grey())),
+            Transform::scale(vec3(2, 2, 2)),
+        );
+        let d = vec3(0.05, 0.0, -1.0);
+        let expected = big.pdf_value(origin, d);
+        assert!((scaled.pdf_value(origin, d) - expected).abs() < 1e-9 * expected);
+        for _ in 0..10 {
+            assert!(big.pdf_value(origin, scaled.random_direction(origin)) > 0.0);
+        }
+    }
+
+    #[test]
+    fn instances_share_their_prototype() {
+        let tree = prototype(
+            vec![
+                Box::new(Sphere::new(vec3(0, 1, 0), 0.5, grey())),
+                Box::new(Sphere::new(vec3(0, 2, 0), 0.3, grey())),
+            ],
+            0.0,
+            1.0,
+        );
+        let forest: Vec<Box<dyn Hittable>> = (0..100)
+            .map(|i| {
+                let at = Transform::translate(vec3(f64::from(i) * 2.0, 0.0, 0.0));
+                Box::new(Instance::new(&tree, at)) as Box<dyn Hittable>
+            })
+            .collect();
+        let world = BVHNode::new(forest, 0.0, 1.0, &None);
+        // the BVH may clone instances, but never the tree itself
+        assert!(Arc::strong_count(&tree) >= 101);
+
+        let r = Ray::new(vec3(42, 10, 0), vec3(0, -1, 0), 0.0);
+        let rec = world.hit(&r, 0.001, f64::MAX).unwrap();
+        assert!((rec.p - vec3(42.0, 2.3, 0.0)).length() < 1e-9);
+        let b = world.bounding_box(0.0, 1.0).unwrap();
+        assert!((b.max().x() - 198.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn instance_material_override() {
+        let ball = prototype(
+            vec![Box::new(Sphere::new(vec3(0, 0, 0), 1.0, grey()))],
+            0.0,
+            1.0,
+        );
+        let lamp = Instance::new(&ball, Transform::translate(vec3(0, 0, -5))).with_material(
+            Arc::new(DiffuseLight {
+                emit: vec3(3, 3, 3),
+            }),
+        );
+        let plain = Instance::new(&ball, Transform::default());
+
+        let r = Ray::new(vec3(0, 0, 0), vec3(0, 0, -1), 0.0);
+        let rec = lamp.hit(&r, 0.001, f64::MAX).unwrap();
+        assert_eq!(rec.material.emitted(&r, &rec), vec3(3, 3, 3));
+        let rec = plain.hit(&r, 0.001, f64::MAX).unwrap();
+        assert_eq!(rec.material.emitted(&r, &rec), vec3(0, 0, 0));
+    }
+}
@@ -1,9 +1,27 @@
+extern crate png;
 extern crate rand;
+extern crate rayon;
+pub mod background;
 pub mod bvh;
 pub mod camera;
+pub mod csg;
+pub mod curve;
+pub mod exr;
+pub mod film;
 pub mod geo;
+pub mod hdr;
+pub mod heightfield;
+pub mod instance;
 pub mod material;
+pub mod mesh;
+pub mod motion;
+pub mod obj;
+pub mod ply;
+pub mod render;
 pub mod scene;
+pub mod scenefile;
+pub mod sdf;
+pub mod tonemap;
 pub mod vec3;
 
 pub use vec3::{Ray, Vec3};
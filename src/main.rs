@@ -1,111 +1,95 @@
-extern crate core;
-extern crate png;
-extern crate rand;
 extern crate rayon;
 extern crate weekend_raytracer;
 
-use png::HasParameters;
-use rand::prelude::*;
-use rayon::prelude::*;
+mod cli;
+
 use std::env;
-use std::fs::File;
-use std::io::BufWriter;
+use std::path::Path;
+use std::process;
 
-use weekend_raytracer::camera::Camera;
-use weekend_raytracer::geo::Hittable;
-use weekend_raytracer::scene::{random_scene, simple_spheres, sphere_tree};
-use weekend_raytracer::vec3::{vec3, Ray, Vec3};
+use cli::Command;
+use weekend_raytracer::film::ImageFormat;
+use weekend_raytracer::render::Renderer;
+use weekend_raytracer::scene::{named_description, SCENE_NAMES};
+use weekend_raytracer::scenefile::SceneDescription;
 
 fn main() {
-    let mul = 4;
-    let nx = mul * 200;
-    let ny = mul * 100;
-    let ns = 10;
-
-    let mut img = vec![0u8; (nx * ny * 3) as usize];
-
-    let look_from = vec3(13, 4, 5);
-    let look_at = vec3(0, 0, 0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
-    let cam = Camera::new(
-        look_from,
-        look_at,
-        vec3(0, 1, 0),
-        20.0,
-        nx as f64 / ny as f64,
-        aperture,
-        dist_to_focus,
-        0.0,
-        1.0,
-    );
-
-    // let world = random_scene();
-    // let world = simple_spheres();
-    let world = sphere_tree();
-    img.par_chunks_mut((nx * 3) as usize)
-        .rev()
-        .enumerate()
-        .for_each(|(j, row)| {
-            let mut rng = thread_rng();
-            for (i, rgb) in row.chunks_mut(3usize).enumerate() {
-                let mut total_color = vec3(0.0, 0.0, 0.0);
-
-                for _ in 0..ns {
-                    let a: f64 = rng.gen();
-                    let b: f64 = rng.gen();
-
-                    let u = (i as f64 + a) / nx as f64;
-                    let v = (j as f64 + b) / ny as f64;
-                    let r = cam.get_ray(u, v);
-                    total_color = total_color + color(&r, &world, 0);
-                }
-
-                let col = total_color / ns as f64;
-
-                let col = vec3(col.x().sqrt(), col.y().sqrt(), col.z().sqrt());
+    let opts = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Render(opts)) => *opts,
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
 
-                let ir = 255.99 * col.x();
-                let ig = 255.99 * col.y();
-                let ib = 255.99 * col.z();
-                let mut iter = rgb.iter_mut();
-                *iter.next().unwrap() = ir as u8;
-                *iter.next().unwrap() = ig as u8;
-                *iter.next().unwrap() = ib as u8;
+    let format = match ImageFormat::from_path(&opts.output) {
+        Some(format) => format,
+        None => {
+            eprintln!(
+                "error: unsupported output {}, expected a .png, .hdr or .exr file",
+                opts.output
+            );
+            process::exit(2);
+        }
+    };
+
+    let mut desc = if Path::new(&opts.scene).is_file() {
+        match SceneDescription::load(&opts.scene) {
+            Ok(desc) => desc,
+            Err(e) => {
+                eprintln!("error: {}:{}", opts.scene, e);
+                process::exit(1);
             }
-        });
-
-    let mut path = env::current_dir().unwrap();
-    path.push(format!("test{}.png", 1));
-    let file = File::create(path).unwrap();
-    let ref mut w = BufWriter::new(file);
+        }
+    } else {
+        match named_description(&opts.scene) {
+            Some(desc) => desc,
+            None => {
+                eprintln!(
+                    "error: unknown scene '{}', expected a scene file or one of {}",
+                    opts.scene,
+                    SCENE_NAMES.join(", ")
+                );
+                process::exit(2);
+            }
+        }
+    };
 
-    let mut encoder = png::Encoder::new(w, nx, ny);
-    encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+    if let Err(e) = opts.apply_camera(&mut desc.camera) {
+        eprintln!("error: {}\n\n{}", e, cli::USAGE);
+        process::exit(2);
+    }
 
-    let mut writer = encoder.write_header().unwrap();
-    writer.write_image_data(&img).unwrap();
-}
+    if let Some(path) = opts.export_scene {
+        if let Err(e) = desc.save(&path) {
+            eprintln!("error: could not write {}: {}", path, e);
+            process::exit(1);
+        }
+        return;
+    }
 
-pub fn color<T: Hittable>(r: &Ray, world: &T, depth: i8) -> Vec3 {
-    match world.hit(r, 0.001, core::f64::MAX) {
-        Some(rec) => {
-            if depth < 50 {
-                let r_clone = rec.clone();
-                match rec.material.scatter(r.clone(), r_clone) {
-                    Some(h) => h.attenuation * color(&h.scattered, world, depth + 1),
+    if let Some(threads) = opts.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("failed to configure the thread pool");
+    }
 
-                    None => vec3(0.0, 0.0, 0.0),
-                }
-            } else {
-                vec3(0.0, 0.0, 0.0)
-            }
+    let scene = match desc.build(opts.settings.aspect()) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("error: could not build {}: {}", opts.scene, e);
+            process::exit(1);
         }
+    };
+    let fb = Renderer::new(opts.settings).render_scene(&scene);
 
-        None => {
-            let unit_direction = r.direction().unit_vector();
-            let t = 0.5 * (unit_direction.y() + 1.0);
-            (1.0 - t) * vec3(1.0, 1.0, 1.0) + t * vec3(0.5, 0.7, 1.0)
-        }
+    if let Err(e) = fb.write(&opts.output, format, &opts.tone) {
+        eprintln!("error: could not write {}: {}", opts.output, e);
+        process::exit(1);
     }
 }
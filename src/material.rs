@@ -1,7 +1,8 @@
 use geo::HitRecord;
 use rand::prelude::*;
+use std::f64::consts::PI;
 use std::fmt::Debug;
-use vec3::{dot, reflect, refract, Ray, Vec3};
+use vec3::{dot, reflect, refract, vec3, Onb, Ray, Vec3};
 
 pub fn schlick(cosine: f64, ref_idx: f64) -> f64 {
     let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
@@ -12,12 +13,48 @@ pub fn schlick(cosine: f64, ref_idx: f64) -> f64 {
 #[derive(Debug)]
 pub struct MaterialReflection {
     pub scattered: Ray,
+    /// The BSDF times cosine divided by `pdf`, i.e. the path throughput weight.
     pub attenuation: Vec3,
     pub hit: bool,
+    /// Solid angle density with which `scattered` was chosen. Meaningless for
+    /// specular reflections.
+    pub pdf: f64,
+    /// Set when the direction came from a delta lobe (mirror or glass) that
+    /// light sampling can never hit.
+    pub is_specular: bool,
+}
+
+pub fn power_heuristic(pdf_f: f64, pdf_g: f64) -> f64 {
+    let f = pdf_f * pdf_f;
+    let g = pdf_g * pdf_g;
+    if f + g == 0.0 {
+        0.0
+    } else {
+        f / (f + g)
+    }
 }
 
 pub trait Material: Sync + Send + Debug {
     fn scatter(&self, r_in: Ray, rec: HitRecord) -> Option<MaterialReflection>;
+
+    /// The BSDF times the cosine term for light leaving along `direction`.
+    ///
+    /// Materials that only scatter into delta directions return `None`, which
+    /// keeps them out of light sampling.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Option<Vec3> {
+        None
+    }
+
+    /// The density with which `scatter` would pick `direction`, 0 for
+    /// materials that only scatter into delta directions.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    /// Radiance given off by the surface at the hit point, black by default.
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec3 {
+        vec3(0.0, 0.0, 0.0)
+    }
 }
 
 #[derive(Debug)]
@@ -25,16 +62,38 @@ pub struct Lambertian {
     pub albedo: Vec3,
 }
 
+impl Lambertian {
+    fn albedo_at(&self, rec: &HitRecord) -> Vec3 {
+        rec.color.unwrap_or(self.albedo)
+    }
+}
+
 impl Material for Lambertian {
     fn scatter(&self, r_in: Ray, rec: HitRecord) -> Option<MaterialReflection> {
-        let target = rec.p + rec.normal + Vec3::random_in_unit_sphere();
-        let scattered = Ray::new(rec.p, target - rec.p, r_in.time());
+        // normal plus a point on the unit sphere is cosine distributed
+        let mut direction = rec.normal + Vec3::random_unit_vector();
+        if direction.squared_length() < 1e-12 {
+            direction = rec.normal;
+        }
+        let scattered = Ray::new(rec.p, direction, r_in.time());
+        let pdf = self.scattering_pdf(&r_in, &rec, direction);
         Some(MaterialReflection {
             scattered,
-            attenuation: self.albedo,
+            attenuation: self.albedo_at(&rec),
             hit: true,
+            pdf,
+            is_specular: false,
         })
     }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Option<Vec3> {
+        let cosine = dot(rec.normal, direction.unit_vector()).max(0.0);
+        Some(self.albedo_at(rec) * cosine / PI)
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        dot(rec.normal, direction.unit_vector()).max(0.0) / PI
+    }
 }
 
 #[derive(Debug)]
@@ -43,24 +102,80 @@ pub struct Metal {
     pub fuzz: f64,
 }
 
+/// Perfect mirrors for `fuzz == 0`, otherwise the mirror direction nudged by a
+/// random point in a ball of radius `fuzz`.
+impl Metal {
+    /// Density of the directions the fuzz ball covers, seen from the hit point:
+    /// the ray along `direction` crosses the ball between `t0` and `t1`.
+    fn lobe_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        let direction = direction.unit_vector();
+        if dot(direction, rec.normal) <= 0.0 {
+            return 0.0;
+        }
+        let reflected = reflect(r_in.direction().unit_vector(), rec.normal).unit_vector();
+        let b = dot(reflected, direction);
+        let discriminant = b * b - 1.0 + self.fuzz * self.fuzz;
+        if discriminant < 0.0 {
+            return 0.0;
+        }
+        let t1 = b + discriminant.sqrt();
+        if t1 <= 0.0 {
+            return 0.0;
+        }
+        let t0 = (b - discriminant.sqrt()).max(0.0);
+        (t1.powi(3) - t0.powi(3)) / (4.0 * PI * self.fuzz.powi(3))
+    }
+}
+
 impl Material for Metal {
     fn scatter(&self, r_in: Ray, rec: HitRecord) -> Option<MaterialReflection> {
         let reflected = reflect(r_in.direction().unit_vector(), rec.normal);
-        let scattered = Ray::new(
-            rec.p,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
-            r_in.time(),
-        );
+        if self.fuzz <= 0.0 {
+            if dot(reflected, rec.normal) <= 0.0 {
+                return None;
+            }
+            return Some(MaterialReflection {
+                scattered: Ray::new(rec.p, reflected, r_in.time()),
+                attenuation: self.albedo,
+                hit: true,
+                pdf: 0.0,
+                is_specular: true,
+            });
+        }
+
+        let direction = reflected + self.fuzz * Vec3::random_in_unit_sphere();
+        let scattered = Ray::new(rec.p, direction, r_in.time());
         if dot(scattered.direction(), rec.normal) > 0.0 {
+            let pdf = self.lobe_pdf(&r_in, &rec, direction);
             Some(MaterialReflection {
-                scattered: scattered.clone(),
+                scattered,
                 attenuation: self.albedo,
                 hit: true,
+                pdf,
+                is_specular: false,
             })
         } else {
             None
         }
     }
+
+    /// The lobe is normalised so that BSDF times cosine is the albedo times
+    /// its own sampling density.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Option<Vec3> {
+        if self.fuzz <= 0.0 {
+            None
+        } else {
+            Some(self.albedo * self.lobe_pdf(r_in, rec, direction))
+        }
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        if self.fuzz <= 0.0 {
+            0.0
+        } else {
+            self.lobe_pdf(r_in, rec, direction)
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -102,8 +217,252 @@ impl Material for Dielectric {
 
         Some(MaterialReflection {
             scattered: scattered.clone(),
-            attenuation: attenuation,
+            attenuation,
             hit: true,
+            pdf: 0.0,
+            is_specular: true,
         })
     }
 }
+
+/// A surface that only emits light and never scatters.
+#[derive(Debug)]
+pub struct DiffuseLight {
+    pub emit: Vec3,
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: Ray, _rec: HitRecord) -> Option<MaterialReflection> {
+        None
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec3 {
+        self.emit
+    }
+}
+
+/// A simple hair fibre shaded around the tangent of the hit, falling back to
+/// an arbitrary direction in the surface for shapes that don't report one.
+///
+/// Light is scattered two ways. A diffuse part goes in all directions,
+/// weighted by the sine of the angle to the fibre as in Kajiya-Kay. A
+/// specular part goes around the cone of mirror reflections off the fibre,
+/// blurred along it by `roughness`.
+#[derive(Debug)]
+pub struct Hair {
+    pub color: Vec3,
+    pub specular: Vec3,
+    /// Spread of the specular cone, in cosine of the angle to the fibre,
+    /// from 0 to 1.
+    pub roughness: f64,
+}
+
+impl Hair {
+    fn tangent(rec: &HitRecord) -> Vec3 {
+        match rec.tangent {
+            Some(tangent) => tangent.unit_vector(),
+            None => Onb::from_w(rec.normal).u,
+        }
+    }
+
+    fn specular_probability(&self) -> f64 {
+        let s = self.specular.max_component();
+        let d = self.color.max_component();
+        if s + d <= 0.0 {
+            0.5
+        } else {
+            s / (s + d)
+        }
+    }
+
+    fn width(&self) -> f64 {
+        self.roughness.clamp(1e-3, 1.0)
+    }
+
+    /// The specular lobe spreads the cosine to the fibre with a triangle
+    /// around the mirror cone, folded back at the poles, and picks the
+    /// angle around the fibre uniformly.
+    fn lobe_pdf(&self, r_in: &Ray, tangent: Vec3, direction: Vec3) -> f64 {
+        let mirror = dot(r_in.direction().unit_vector(), tangent);
+        let z = dot(direction.unit_vector(), tangent);
+        let w = self.width();
+        let kernel = |x: f64| (w - x.abs()).max(0.0) / (w * w);
+        (kernel(z - mirror) + kernel(2.0 - z - mirror) + kernel(-2.0 - z - mirror)) / (2.0 * PI)
+    }
+}
+
+impl Material for Hair {
+    fn scatter(&self, r_in: Ray, rec: HitRecord) -> Option<MaterialReflection> {
+        let mut rng = thread_rng();
+        let tangent = Hair::tangent(&rec);
+        let direction = if rng.gen::<f64>() < self.specular_probability() {
+            let mirror = dot(r_in.direction().unit_vector(), tangent);
+            let mut z = mirror + (rng.gen::<f64>() - rng.gen::<f64>()) * self.width();
+            if z > 1.0 {
+                z = 2.0 - z;
+            } else if z < -1.0 {
+                z = -2.0 - z;
+            }
+            let phi = 2.0 * PI * rng.gen::<f64>();
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            Onb::from_w(tangent).local(vec3(r * phi.cos(), r * phi.sin(), z))
+        } else {
+            Vec3::random_unit_vector()
+        };
+
+        let pdf = self.scattering_pdf(&r_in, &rec, direction);
+        let f = self.eval(&r_in, &rec, direction)?;
+        Some(MaterialReflection {
+            scattered: Ray::new(rec.p, direction, r_in.time()),
+            attenuation: f / pdf,
+            hit: true,
+            pdf,
+            is_specular: false,
+        })
+    }
+
+    /// The diffuse part integrates to `color` over the sphere and the
+    /// specular part to `specular`.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Option<Vec3> {
+        let tangent = Hair::tangent(rec);
+        let cos = dot(direction.unit_vector(), tangent);
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        Some(self.color * sin / (PI * PI) + self.specular * self.lobe_pdf(r_in, tangent, direction))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        let p = self.specular_probability();
+        p * self.lobe_pdf(r_in, Hair::tangent(rec), direction) + (1.0 - p) / (4.0 * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn hit_from_above() -> (Ray, HitRecord) {
+        let material: Arc<dyn Material> = Arc::new(Lambertian {
+            albedo: vec3(1, 1, 1),
+        });
+        let r_in = Ray::new(vec3(-1, 1, 0), vec3(1, -1, 0), 0.0);
+        let rec = HitRecord {
+            t: 1.0,
+            p: vec3(0, 0, 0),
+            normal: vec3(0, 1, 0),
+            u: 0.0,
+            v: 0.0,
+            material,
+            tangent: None,
+            color: None,
+        };
+        (r_in, rec)
+    }
+
+    #[test]
+    fn sampled_weight_matches_eval_over_pdf() {
+        let (r_in, rec) = hit_from_above();
+        let materials: Vec<Box<dyn Material>> = vec![
+            Box::new(Lambertian {
+                albedo: vec3(0.5, 0.6, 0.7),
+            }),
+            Box::new(Metal {
+                albedo: vec3(0.8, 0.6, 0.2),
+                fuzz: 0.3,
+            }),
+        ];
+
+        for m in &materials {
+            for _ in 0..100 {
+                if let Some(h) = m.scatter(r_in.clone(), rec.clone()) {
+                    let dir = h.scattered.direction();
+                    let f = m.eval(&r_in, &rec, dir).unwrap();
+                    let pdf = m.scattering_pdf(&r_in, &rec, dir);
+                    assert!(!h.is_specular);
+                    assert!((h.pdf - pdf).abs() < 1e-9 * pdf.max(1.0));
+                    assert!((f / pdf - h.attenuation).length() < 1e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_ball_pdf_integrates_to_one() {
+        // straight down, so the whole ball of directions sits above the surface
+        let (_, rec) = hit_from_above();
+        let r_in = Ray::new(vec3(0, 1, 0), vec3(0, -1, 0), 0.0);
+        let metal = Metal {
+            albedo: vec3(1, 1, 1),
+            fuzz: 0.3,
+        };
+
+        // the pdf only depends on the angle to the mirror direction, and is
+        // zero outside the cone the ball subtends
+        let cos_edge = (1.0 - 0.3 * 0.3f64).sqrt();
+        let steps = 10_000;
+        let dc = (1.0 - cos_edge) / steps as f64;
+        let total: f64 = (0..steps)
+            .map(|i| {
+                let c = cos_edge + (i as f64 + 0.5) * dc;
+                let direction = vec3((1.0 - c * c).sqrt(), c, 0.0);
+                2.0 * PI * metal.scattering_pdf(&r_in, &rec, direction) * dc
+            })
+            .sum();
+        assert!((total - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn mirrors_and_glass_are_specular() {
+        let (r_in, rec) = hit_from_above();
+        let mirror = Metal {
+            albedo: vec3(1, 1, 1),
+            fuzz: 0.0,
+        };
+        let glass = Dielectric { ref_idx: 1.5 };
+
+        assert!(
+            mirror
+                .scatter(r_in.clone(), rec.clone())
+                .unwrap()
+                .is_specular
+        );
+        assert!(
+            glass
+                .scatter(r_in.clone(), rec.clone())
+                .unwrap()
+                .is_specular
+        );
+        assert!(mirror.eval(&r_in, &rec, vec3(1, 1, 0)).is_none());
+        assert_eq!(glass.scattering_pdf(&r_in, &rec, vec3(1, 1, 0)), 0.0);
+    }
+
+    #[test]
+    fn hair_scatters_around_the_fibre() {
+        let (r_in, mut rec) = hit_from_above();
+        rec.tangent = Some(vec3(0, 0, 1));
+        let hair = Hair {
+            color: vec3(0.3, 0.2, 0.1),
+            specular: vec3(0.5, 0.5, 0.5),
+            roughness: 0.1,
+        };
+        for _ in 0..200 {
+            let h = hair.scatter(r_in.clone(), rec.clone()).unwrap();
+            let dir = h.scattered.direction();
+            let f = hair.eval(&r_in, &rec, dir).unwrap();
+            let pdf = hair.scattering_pdf(&r_in, &rec, dir);
+            assert!((h.pdf - pdf).abs() < 1e-9 * pdf.max(1.0));
+            assert!((f / pdf - h.attenuation).length() < 1e-6);
+        }
+
+        // with no diffuse part every direction lies near the mirror cone,
+        // which for a ray across the fibre is the plane across it
+        let shiny = Hair {
+            color: vec3(0, 0, 0),
+            ..hair
+        };
+        for _ in 0..200 {
+            let h = shiny.scatter(r_in.clone(), rec.clone()).unwrap();
+            assert!(h.scattered.direction().unit_vector().z().abs() <= 0.1 + 1e-9);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//!
+//! Indexed triangle meshes whose buffers are stored once and shared by every
+//! triangle that refers to them
+use bvh::{LinearBvh, AABB};
+use geo::{intersect_triangle, points_bounding_box, BvhBuilder, HitRecord, Hittable};
+use material::Material;
+use std::sync::Arc;
+use vec3::{Ray, Vec3};
+
+/// Vertex and index buffers for a mesh. `normals`, `uvs` and `colors` are
+/// either empty or hold one entry per position.
+#[derive(Clone, Debug)]
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    /// Per-vertex albedo. The interpolated colour is handed to `material`
+    /// through `HitRecord::color`.
+    pub colors: Vec<Vec3>,
+    pub indices: Vec<[u32; 3]>,
+    pub material: Arc<dyn Material>,
+}
+
+impl MeshData {
+    pub fn new(
+        positions: Vec<Vec3>,
+        indices: Vec<[u32; 3]>,
+        material: Arc<dyn Material>,
+    ) -> MeshData {
+        assert!(
+            indices
+                .iter()
+                .all(|tri| tri.iter().all(|&i| (i as usize) < positions.len())),
+            "triangle index out of range"
+        );
+        MeshData {
+            positions,
+            normals: vec![],
+            uvs: vec![],
+            colors: vec![],
+            indices,
+            material,
+        }
+    }
+
+    pub fn with_normals(self, normals: Vec<Vec3>) -> MeshData {
+        assert_eq!(normals.len(), self.positions.len());
+        MeshData { normals, ..self }
+    }
+
+    pub fn with_uvs(self, uvs: Vec<(f64, f64)>) -> MeshData {
+        assert_eq!(uvs.len(), self.positions.len());
+        MeshData { uvs, ..self }
+    }
+
+    pub fn with_colors(self, colors: Vec<Vec3>) -> MeshData {
+        assert_eq!(colors.len(), self.positions.len());
+        MeshData { colors, ..self }
+    }
+
+    /// One lightweight primitive per triangle, each pointing back into the
+    /// shared buffers.
+    pub fn triangles(data: &Arc<MeshData>) -> Vec<Box<dyn Hittable>> {
+        (0..data.indices.len())
+            .map(|index| {
+                Box::new(MeshTriangle {
+                    mesh: Arc::clone(data),
+                    index,
+                }) as Box<dyn Hittable>
+            })
+            .collect()
+    }
+
+    fn vertices(&self, index: usize) -> [usize; 3] {
+        let [a, b, c] = self.indices[index];
+        [a as usize, b as usize, c as usize]
+    }
+}
+
+/// The `index`th triangle of a mesh.
+#[derive(Clone, Debug)]
+pub struct MeshTriangle {
+    pub mesh: Arc<MeshData>,
+    pub index: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mesh = &*self.mesh;
+        let [i0, i1, i2] = mesh.vertices(self.index);
+        let (v0, v1, v2) = (mesh.positions[i0], mesh.positions[i1], mesh.positions[i2]);
+        let (t, b1, b2) = intersect_triangle(r, v0, v1, v2, t_min, t_max)?;
+        let b0 = 1.0 - b1 - b2;
+
+        let normal = if mesh.normals.is_empty() {
+            (v1 - v0).cross(v2 - v0)
+        } else {
+            b0 * mesh.normals[i0] + b1 * mesh.normals[i1] + b2 * mesh.normals[i2]
+        };
+        let (u, v) = if mesh.uvs.is_empty() {
+            (b1, b2)
+        } else {
+            let (uv0, uv1, uv2) = (mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]);
+            (
+                b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+                b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+            )
+        };
+
+        let color = if mesh.colors.is_empty() {
+            None
+        } else {
+            Some(b0 * mesh.colors[i0] + b1 * mesh.colors[i1] + b2 * mesh.colors[i2])
+        };
+
+        Some(HitRecord {
+            t,
+            p: r.point_at_parameter(t),
+            normal: normal.unit_vector(),
+            u,
+            v,
+            material: Arc::clone(&mesh.material),
+            tangent: None,
+            color,
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        let [i0, i1, i2] = self.mesh.vertices(self.index);
+        let p = &self.mesh.positions;
+        Some(points_bounding_box(&[p[i0], p[i1], p[i2]]))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+/// A mesh with its own BVH over its triangles. Cloning it only bumps
+/// reference counts.
+#[derive(Clone, Debug)]
+pub struct TriangleMesh {
+    pub data: Arc<MeshData>,
+    bvh: Arc<LinearBvh>,
+}
+
+impl TriangleMesh {
+    pub fn new(data: MeshData) -> TriangleMesh {
+        let data = Arc::new(data);
+        let triangles = MeshData::triangles(&data);
+        let bvh = Arc::new(LinearBvh::new(triangles, 0.0, 0.0, BvhBuilder::default()));
+        TriangleMesh { data, bvh }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.indices.is_empty()
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.bvh.bounding_box(t0, t1)
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::tests::grey;
+    use std::f64;
+    use vec3::vec3;
+
+    fn quad() -> MeshData {
+        MeshData::new(
+            vec![vec3(0, 0, 0), vec3(1, 0, 0), vec3(1, 1, 0), vec3(0, 1, 0)],
+            vec![[0, 1, 2], [0, 2, 3]],
+            grey(),
+        )
+    }
+
+    #[test]
+    fn mesh_hits_both_triangles() {
+        let mesh = TriangleMesh::new(quad());
+        assert_eq!(mesh.len(), 2);
+        for &(x, y) in &[(0.75, 0.25), (0.25, 0.75)] {
+            let r = Ray::new(vec3(x, y, 1.0), vec3(0, 0, -1), 0.0);
+            let rec = mesh.hit(&r, 0.001, f64::MAX).unwrap();
+            assert!((rec.t - 1.0).abs() < 1e-12);
+            assert_eq!(rec.normal, vec3(0, 0, 1));
+        }
+        let miss = Ray::new(vec3(1.5, 0.5, 1.0), vec3(0, 0, -1), 0.0);
+        assert!(mesh.hit(&miss, 0.001, f64::MAX).is_none());
+
+        let b = mesh.bounding_box(0.0, 0.0).unwrap();
+        assert!(b.min().x() <= 0.0 && b.max().y() >= 1.0);
+    }
+
+    #[test]
+    fn triangles_share_buffers() {
+        let data = Arc::new(quad().with_uvs(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]));
+        let triangles = MeshData::triangles(&data);
+        assert_eq!(Arc::strong_count(&data), 3);
+
+        let r = Ray::new(vec3(0.25, 0.75, 1.0), vec3(0, 0, -1), 0.0);
+        let rec = triangles[1].hit(&r, 0.001, f64::MAX).unwrap();
+        assert!((rec.u - 0.25).abs() < 1e-12 && (rec.v - 0.75).abs() < 1e-12);
+        assert!(triangles[0].hit(&r, 0.001, f64::MAX).is_none());
+    }
+}
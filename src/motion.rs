@@ -0,0 +1,287 @@
+//!
+//! Transforms that change over the shutter interval, so that anything can be
+//! motion blurred.
+use bvh::{surrounding_box, AABB};
+use geo::{HitRecord, Hittable};
+use instance::{hit_transformed, matrix_box};
+use vec3::{vec3, Mat4, Quat, Ray, Transform, Vec3};
+
+/// Samples taken between each pair of times when sweeping a bounding box.
+const SWEEP_STEPS: usize = 16;
+
+/// A pose at one moment: scaled, then rotated, then moved. Keeping the parts
+/// apart lets the rotation be interpolated on its own.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    /// The identity pose at `time`.
+    pub fn new(time: f64) -> Keyframe {
+        Keyframe {
+            time,
+            translation: vec3(0, 0, 0),
+            rotation: Quat::default(),
+            scale: vec3(1, 1, 1),
+        }
+    }
+
+    pub fn with_translation(self, translation: Vec3) -> Keyframe {
+        Keyframe {
+            translation,
+            ..self
+        }
+    }
+
+    pub fn with_rotation(self, axis: Vec3, degrees: f64) -> Keyframe {
+        Keyframe {
+            rotation: Quat::from_axis_angle(axis, degrees),
+            ..self
+        }
+    }
+
+    pub fn with_scale(self, scale: Vec3) -> Keyframe {
+        Keyframe { scale, ..self }
+    }
+
+    /// `None` when a scale factor is zero, which flattens the object to
+    /// nothing that a ray could hit.
+    pub fn transform(&self) -> Option<Transform> {
+        if self.is_degenerate() {
+            return None;
+        }
+        Some(
+            Transform::scale(self.scale)
+                .then(&Transform::from_quat(self.rotation))
+                .then(&Transform::translate(self.translation)),
+        )
+    }
+
+    /// The pose as a matrix, defined even when it can't be inverted.
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::translate(self.translation) * self.rotation.to_mat4() * Mat4::scale(self.scale)
+    }
+
+    pub fn is_degenerate(&self) -> bool {
+        self.scale.x() == 0.0 || self.scale.y() == 0.0 || self.scale.z() == 0.0
+    }
+
+    /// The pose a fraction `s` of the way to `next`. Translation and scale
+    /// are interpolated linearly and rotation at a constant angular speed.
+    fn lerp(&self, next: &Keyframe, s: f64) -> Keyframe {
+        Keyframe {
+            time: (1.0 - s) * self.time + s * next.time,
+            translation: (1.0 - s) * self.translation + s * next.translation,
+            rotation: self.rotation.slerp(next.rotation, s),
+            scale: (1.0 - s) * self.scale + s * next.scale,
+        }
+    }
+}
+
+/// A transform given by keyframes. Before the first and after the last key
+/// it holds still.
+#[derive(Clone, Debug)]
+pub struct AnimatedTransform {
+    keyframes: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    /// Panics without any keyframes. They may be given in any order.
+    pub fn new(mut keyframes: Vec<Keyframe>) -> AnimatedTransform {
+        assert!(!keyframes.is_empty(), "an animation needs a keyframe");
+        keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        AnimatedTransform { keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn pose(&self, time: f64) -> Keyframe {
+        let keys = &self.keyframes;
+        let next = keys.iter().position(|k| k.time > time);
+        match next {
+            Some(0) => keys[0],
+            Some(i) => {
+                let (a, b) = (&keys[i - 1], &keys[i]);
+                a.lerp(b, (time - a.time) / (b.time - a.time))
+            }
+            None => keys[keys.len() - 1],
+        }
+    }
+
+    /// `None` while the object is scaled down to nothing.
+    pub fn at(&self, time: f64) -> Option<Transform> {
+        self.pose(time).transform()
+    }
+
+    /// Bounds of `b` over every transform between `t0` and `t1`.
+    ///
+    /// The box is sampled at times that include every keyframe. Between two
+    /// samples, freezing the rotation leaves a motion that is affine in time
+    /// and so stays within the samples' box. The rotation can then move a
+    /// point by at most its distance from the origin times the angle turned,
+    /// so the box is padded by twice that.
+    pub fn sweep_box(&self, b: &AABB, t0: f64, t1: f64) -> AABB {
+        let mut stops = vec![t0];
+        stops.extend(
+            self.keyframes
+                .iter()
+                .map(|k| k.time)
+                .filter(|&t| t > t0 && t < t1),
+        );
+        stops.push(t1);
+
+        let mut times = vec![t0];
+        for pair in stops.windows(2) {
+            for step in 1..=SWEEP_STEPS {
+                let s = step as f64 / SWEEP_STEPS as f64;
+                times.push((1.0 - s) * pair[0] + s * pair[1]);
+            }
+        }
+
+        let corner = b
+            .min()
+            .map(f64::abs)
+            .apply_per_element(b.max().map(f64::abs), f64::max);
+        let largest_scale = self
+            .keyframes
+            .iter()
+            .map(|k| k.scale.map(f64::abs).max_component())
+            .fold(0.0, f64::max);
+        let reach = corner.length() * largest_scale;
+
+        let poses: Vec<Keyframe> = times.iter().map(|&t| self.pose(t)).collect();
+        let mut swept = matrix_box(&poses[0].matrix(), b);
+        let mut turn: f64 = 0.0;
+        for pair in poses.windows(2) {
+            swept = surrounding_box(&swept, &matrix_box(&pair[1].matrix(), b));
+            turn = turn.max(pair[0].rotation.angle_to(pair[1].rotation));
+        }
+
+        let pad = 2.0 * turn * reach;
+        let pad = vec3(pad, pad, pad);
+        AABB {
+            min: swept.min - pad,
+            max: swept.max + pad,
+        }
+    }
+}
+
+/// An object that moves, turns or grows over time. The ray's time picks the
+/// transform, so any shape, mesh or instance can be motion blurred.
+#[derive(Clone, Debug)]
+pub struct Animated {
+    pub inner: Box<dyn Hittable>,
+    pub motion: AnimatedTransform,
+}
+
+impl Animated {
+    pub fn new(inner: Box<dyn Hittable>, motion: AnimatedTransform) -> Animated {
+        Animated { inner, motion }
+    }
+}
+
+impl Hittable for Animated {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let transform = self.motion.at(r.time())?;
+        hit_transformed(&*self.inner, &transform, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.inner
+            .bounding_box(t0, t1)
+            .map(|b| self.motion.sweep_box(&b, t0, t1))
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::tests::ball;
+    use std::f64;
+
+    fn contains(b: &AABB, p: Vec3) -> bool {
+        (0..3).all(|a| b.min()[a] <= p[a] && p[a] <= b.max()[a])
+    }
+
+    #[test]
+    fn follows_keyframes() {
+        let motion = AnimatedTransform::new(vec![
+            Keyframe::new(2.0).with_translation(vec3(10, 10, 0)),
+            Keyframe::new(0.0),
+            Keyframe::new(1.0).with_translation(vec3(10, 0, 0)),
+        ]);
+        let moving = Animated::new(ball(vec3(0, 0, 0), 1.0), motion);
+
+        let at = |time: f64, x: f64, y: f64| {
+            let r = Ray::new(vec3(x, y, 5.0), vec3(0, 0, -1), time);
+            moving.hit(&r, 0.001, f64::MAX).map(|rec| rec.p)
+        };
+        assert!((at(0.5, 5.0, 0.0).unwrap() - vec3(5, 0, 1)).length() < 1e-9);
+        assert!((at(1.5, 10.0, 5.0).unwrap() - vec3(10, 5, 1)).length() < 1e-9);
+        assert!(at(0.5, 0.0, 0.0).is_none());
+        // holds still outside the keys
+        assert!(at(-1.0, 0.0, 0.0).is_some());
+        assert!(at(3.0, 10.0, 10.0).is_some());
+
+        let b = moving.bounding_box(0.0, 1.0).unwrap();
+        assert!((b.min().x() + 1.0).abs() < 1e-9 && (b.max().x() - 11.0).abs() < 1e-9);
+        assert!((b.max().y() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn swept_box_covers_rotation() {
+        let motion = AnimatedTransform::new(vec![
+            Keyframe::new(0.0),
+            Keyframe::new(1.0)
+                .with_rotation(vec3(0, 1, 0), 180.0)
+                .with_scale(vec3(2, 2, 2)),
+        ]);
+        let spinning = Animated::new(ball(vec3(3, 0, 0), 0.5), motion.clone());
+        let b = spinning.bounding_box(0.0, 1.0).unwrap();
+
+        for i in 0..=100 {
+            let time = i as f64 / 100.0;
+            let pose = motion.at(time).unwrap();
+            let scale = motion.pose(time).scale.x();
+            for offset in &[vec3(1, 0, 0), vec3(-1, 0, 0), vec3(0, 0, 1), vec3(0, 0, -1)] {
+                let p = pose.point(vec3(3, 0, 0)) + scale * 0.5 * *offset;
+                assert!(contains(&b, p), "{:?} at {} outside {:?}", p, time, b);
+            }
+        }
+        // half a turn ends up on the other side
+        let r = Ray::new(vec3(-6, 5, 0), vec3(0, -1, 0), 1.0);
+        assert!((spinning.hit(&r, 0.001, f64::MAX).unwrap().p.y() - 1.0).abs() < 1e-9);
+
+        let still = spinning.bounding_box(0.0, 0.0).unwrap();
+        assert!(still.max().x() <= 3.5 + 1e-9 && still.min().x() >= 2.5 - 1e-9);
+    }
+
+    #[test]
+    fn grows_from_nothing() {
+        let motion = AnimatedTransform::new(vec![
+            Keyframe::new(0.0).with_scale(vec3(0, 0, 0)),
+            Keyframe::new(1.0).with_translation(vec3(5, 0, 0)),
+        ]);
+        let growing = Animated::new(ball(vec3(0, 0, 0), 1.0), motion);
+        let b = growing.bounding_box(0.0, 1.0).unwrap();
+        assert!(contains(&b, vec3(0, 0, 0)) && contains(&b, vec3(6, 1, 1)));
+        assert!(growing.bounding_box(0.0, 0.0).is_some());
+
+        let at = |time: f64| {
+            let r = Ray::new(vec3(5, 0, 5), vec3(0, 0, -1), time);
+            growing.hit(&r, 0.001, f64::MAX).map(|rec| rec.p)
+        };
+        assert!(at(0.0).is_none());
+        assert!((at(1.0).unwrap() - vec3(5, 0, 1)).length() < 1e-9);
+    }
+}
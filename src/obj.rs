@@ -0,0 +1,538 @@
+//!
+//! Wavefront OBJ and MTL import.
+//!
+//! Faces are fan triangulated and split into one `TriangleMesh` per group or
+//! object and material. Statements the renderer has no use for (smoothing
+//! groups, lines, points, texture maps) are skipped.
+use geo::{Hittable, HittableList};
+use material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use mesh::{MeshData, TriangleMesh};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use vec3::{vec3, Vec3};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    /// A malformed statement, with its 1-based line number.
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObjError::Io(ref e) => write!(f, "{}", e),
+            ObjError::Parse { line, ref message } => write!(f, "{}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for ObjError {}
+
+impl From<io::Error> for ObjError {
+    fn from(e: io::Error) -> ObjError {
+        ObjError::Io(e)
+    }
+}
+
+fn parse_error<S: Into<String>>(line: usize, message: S) -> ObjError {
+    ObjError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Splits a line into its keyword and arguments, dropping comments.
+fn statement(line: &str) -> Option<(&str, Vec<&str>)> {
+    let line = match line.find('#') {
+        Some(idx) => &line[..idx],
+        None => line,
+    };
+    let mut words = line.split_whitespace();
+    let keyword = words.next()?;
+    Some((keyword, words.collect()))
+}
+
+fn parse_floats(line: usize, args: &[&str], min: usize, max: usize) -> Result<Vec<f64>, ObjError> {
+    if args.len() < min || args.len() > max {
+        return Err(parse_error(
+            line,
+            format!("expected {} to {} numbers, found {}", min, max, args.len()),
+        ));
+    }
+    args.iter()
+        .map(|a| {
+            a.parse::<f64>()
+                .map_err(|_| parse_error(line, format!("invalid number '{}'", a)))
+        })
+        .collect()
+}
+
+fn parse_color(line: usize, args: &[&str]) -> Result<Vec3, ObjError> {
+    let c = parse_floats(line, args, 1, 3)?;
+    // a single value means grey
+    match c.len() {
+        1 => Ok(vec3(c[0], c[0], c[0])),
+        3 => Ok(vec3(c[0], c[1], c[2])),
+        _ => Err(parse_error(line, "expected 1 or 3 colour components")),
+    }
+}
+
+/// The subset of an MTL material the renderer understands.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MtlMaterial {
+    /// Diffuse colour.
+    pub kd: Vec3,
+    /// Specular colour.
+    pub ks: Vec3,
+    /// Specular exponent.
+    pub ns: f64,
+    /// Index of refraction.
+    pub ni: f64,
+    /// Opacity; anything below 1 is treated as glass.
+    pub d: f64,
+    /// Emitted radiance.
+    pub ke: Vec3,
+    /// Illumination model; 3 and 5 ask for ray traced reflections.
+    pub illum: u32,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> MtlMaterial {
+        MtlMaterial {
+            kd: vec3(0.8, 0.8, 0.8),
+            ks: vec3(0, 0, 0),
+            ns: 0.0,
+            ni: 1.0,
+            d: 1.0,
+            ke: vec3(0, 0, 0),
+            illum: 2,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Picks the closest material: emissive if `Ke` is set, glass if
+    /// transparent, metal if there is a specular colour and either no diffuse
+    /// colour or an `illum` asking for reflections, diffuse otherwise.
+    pub fn build(&self) -> Arc<dyn Material> {
+        let reflective = self.kd.max_component() <= 0.0 || self.illum == 3 || self.illum == 5;
+        if self.ke.max_component() > 0.0 {
+            Arc::new(DiffuseLight { emit: self.ke })
+        } else if self.d < 1.0 {
+            Arc::new(Dielectric {
+                ref_idx: if self.ni > 0.0 { self.ni } else { 1.5 },
+            })
+        } else if self.ks.max_component() > 0.0 && reflective {
+            // a fuzz ball of radius sqrt(2 / (Ns + 2)) spreads about as wide
+            // as a Phong lobe of exponent Ns
+            Arc::new(Metal {
+                albedo: self.ks,
+                fuzz: (2.0 / (self.ns.max(0.0) + 2.0)).sqrt(),
+            })
+        } else {
+            Arc::new(Lambertian { albedo: self.kd })
+        }
+    }
+}
+
+/// Parses an MTL library into materials by name.
+pub fn parse_mtl(src: &str) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (idx, line) in src.lines().enumerate() {
+        let line_no = idx + 1;
+        let (keyword, args) = match statement(line) {
+            Some(s) => s,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            if args.len() != 1 {
+                return Err(parse_error(line_no, "newmtl expects a name"));
+            }
+            if let Some((name, m)) = current.take() {
+                materials.insert(name, m);
+            }
+            current = Some((args[0].to_string(), MtlMaterial::default()));
+            continue;
+        }
+
+        let m = match current {
+            Some((_, ref mut m)) => m,
+            None => return Err(parse_error(line_no, format!("'{}' before newmtl", keyword))),
+        };
+        match keyword {
+            "Kd" => m.kd = parse_color(line_no, &args)?,
+            "Ks" => m.ks = parse_color(line_no, &args)?,
+            "Ke" => m.ke = parse_color(line_no, &args)?,
+            "Ns" => m.ns = parse_floats(line_no, &args, 1, 1)?[0],
+            "Ni" => m.ni = parse_floats(line_no, &args, 1, 1)?[0],
+            "d" => m.d = parse_floats(line_no, &args, 1, 1)?[0],
+            "Tr" => m.d = 1.0 - parse_floats(line_no, &args, 1, 1)?[0],
+            "illum" => m.illum = parse_floats(line_no, &args, 1, 1)?[0] as u32,
+            _ => (),
+        }
+    }
+
+    if let Some((name, m)) = current {
+        materials.insert(name, m);
+    }
+    Ok(materials)
+}
+
+/// Zero based indices of a face corner into the position, UV and normal lists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObjVertex {
+    pub position: usize,
+    pub uv: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+/// Triangles that share an object/group name and a material.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjGroup {
+    pub name: String,
+    pub material: Option<String>,
+    pub triangles: Vec<[ObjVertex; 3]>,
+}
+
+/// The contents of an OBJ file, before materials are resolved.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjFile {
+    pub positions: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub normals: Vec<Vec3>,
+    /// Libraries named by `mtllib`, relative to the OBJ file.
+    pub material_libs: Vec<String>,
+    pub groups: Vec<ObjGroup>,
+}
+
+/// Resolves a 1-based, possibly negative (relative to the end) OBJ index.
+fn resolve_index(line: usize, text: &str, len: usize) -> Result<usize, ObjError> {
+    let i: i64 = text
+        .parse()
+        .map_err(|_| parse_error(line, format!("invalid index '{}'", text)))?;
+    let resolved = if i < 0 { len as i64 + i } else { i - 1 };
+    if i == 0 || resolved < 0 || resolved >= len as i64 {
+        return Err(parse_error(line, format!("index {} out of range", i)));
+    }
+    Ok(resolved as usize)
+}
+
+impl ObjFile {
+    pub fn parse(src: &str) -> Result<ObjFile, ObjError> {
+        let mut obj = ObjFile::default();
+        let mut group = ObjGroup::default();
+
+        for (idx, line) in src.lines().enumerate() {
+            let line_no = idx + 1;
+            let (keyword, args) = match statement(line) {
+                Some(s) => s,
+                None => continue,
+            };
+            match keyword {
+                "v" => {
+                    // an optional w or vertex colour may follow
+                    let p = parse_floats(line_no, &args, 3, 7)?;
+                    obj.positions.push(vec3(p[0], p[1], p[2]));
+                }
+                "vt" => {
+                    let t = parse_floats(line_no, &args, 1, 3)?;
+                    obj.uvs.push((t[0], t.get(1).cloned().unwrap_or(0.0)));
+                }
+                "vn" => {
+                    let n = parse_floats(line_no, &args, 3, 3)?;
+                    obj.normals.push(vec3(n[0], n[1], n[2]));
+                }
+                "f" => {
+                    if args.len() < 3 {
+                        return Err(parse_error(line_no, "a face needs at least 3 vertices"));
+                    }
+                    let corners = args
+                        .iter()
+                        .map(|a| obj.face_vertex(line_no, a))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    for i in 1..corners.len() - 1 {
+                        group
+                            .triangles
+                            .push([corners[0], corners[i], corners[i + 1]]);
+                    }
+                }
+                "g" | "o" => {
+                    let next = ObjGroup {
+                        name: args.join(" "),
+                        material: group.material.clone(),
+                        triangles: vec![],
+                    };
+                    obj.push_group(group);
+                    group = next;
+                }
+                "usemtl" => {
+                    if args.len() != 1 {
+                        return Err(parse_error(line_no, "usemtl expects a name"));
+                    }
+                    let next = ObjGroup {
+                        name: group.name.clone(),
+                        material: Some(args[0].to_string()),
+                        triangles: vec![],
+                    };
+                    obj.push_group(group);
+                    group = next;
+                }
+                "mtllib" => obj.material_libs.extend(args.iter().map(|a| a.to_string())),
+                _ => (),
+            }
+        }
+
+        obj.push_group(group);
+        Ok(obj)
+    }
+
+    /// Parses a `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner.
+    fn face_vertex(&self, line: usize, text: &str) -> Result<ObjVertex, ObjError> {
+        let mut parts = text.split('/');
+        let position = resolve_index(line, parts.next().unwrap_or(""), self.positions.len())?;
+        let uv = match parts.next() {
+            Some(t) if !t.is_empty() => Some(resolve_index(line, t, self.uvs.len())?),
+            _ => None,
+        };
+        let normal = match parts.next() {
+            Some(n) if !n.is_empty() => Some(resolve_index(line, n, self.normals.len())?),
+            _ => None,
+        };
+        if parts.next().is_some() {
+            return Err(parse_error(line, format!("invalid face vertex '{}'", text)));
+        }
+        Ok(ObjVertex {
+            position,
+            uv,
+            normal,
+        })
+    }
+
+    fn push_group(&mut self, group: ObjGroup) {
+        if !group.triangles.is_empty() {
+            self.groups.push(group);
+        }
+    }
+
+    /// Builds one mesh per group. Groups whose material is unknown or unset
+    /// use `default`.
+    pub fn build(
+        &self,
+        materials: &HashMap<String, Arc<dyn Material>>,
+        default: &Arc<dyn Material>,
+    ) -> Vec<TriangleMesh> {
+        self.groups
+            .iter()
+            .map(|g| {
+                let material = g
+                    .material
+                    .as_ref()
+                    .and_then(|name| materials.get(name))
+                    .unwrap_or(default);
+                TriangleMesh::new(self.mesh_data(g, Arc::clone(material)))
+            })
+            .collect()
+    }
+
+    /// Collects the distinct corners of a group into shared vertex buffers.
+    fn mesh_data(&self, group: &ObjGroup, material: Arc<dyn Material>) -> MeshData {
+        let mut remap: HashMap<ObjVertex, u32> = HashMap::new();
+        let mut corners = vec![];
+        let indices = group
+            .triangles
+            .iter()
+            .map(|tri| {
+                let mut out = [0; 3];
+                for (o, corner) in out.iter_mut().zip(tri) {
+                    *o = *remap.entry(*corner).or_insert_with(|| {
+                        corners.push(*corner);
+                        corners.len() as u32 - 1
+                    });
+                }
+                out
+            })
+            .collect();
+
+        let positions = corners.iter().map(|c| self.positions[c.position]).collect();
+        let mut data = MeshData::new(positions, indices, material);
+        // normals and UVs are all or nothing per mesh
+        if corners.iter().all(|c| c.normal.is_some()) {
+            data = data.with_normals(
+                corners
+                    .iter()
+                    .map(|c| self.normals[c.normal.unwrap()])
+                    .collect(),
+            );
+        }
+        if corners.iter().all(|c| c.uv.is_some()) {
+            data = data.with_uvs(corners.iter().map(|c| self.uvs[c.uv.unwrap()]).collect());
+        }
+        data
+    }
+}
+
+/// Loads an OBJ file and the MTL libraries it names, ready to be added to a
+/// `HittableList` or `BVHNode`.
+pub fn load_obj<P: AsRef<Path>>(
+    path: P,
+    default: Arc<dyn Material>,
+) -> Result<Box<dyn Hittable>, ObjError> {
+    let path = path.as_ref();
+    let obj = ObjFile::parse(&fs::read_to_string(path)?)?;
+
+    let mut materials = HashMap::new();
+    for lib in &obj.material_libs {
+        let lib_path = match path.parent() {
+            Some(dir) => dir.join(lib),
+            None => Path::new(lib).to_path_buf(),
+        };
+        for (name, m) in parse_mtl(&fs::read_to_string(lib_path)?)? {
+            materials.insert(name, m.build());
+        }
+    }
+
+    let list = obj
+        .build(&materials, &default)
+        .into_iter()
+        .map(|m| Box::new(m) as Box<dyn Hittable>)
+        .collect();
+    Ok(Box::new(HittableList { list }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::tests::grey;
+    use geo::HitRecord;
+    use std::f64;
+    use vec3::Ray;
+
+    const QUAD: &str = "
+mtllib scene.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vn 0 0 1
+o floor
+usemtl red
+f 1/1/1 2/1/1 3/1/1 4/1/1
+g lamp
+usemtl light
+f -4//1 -2//1 -1//1
+";
+
+    #[test]
+    fn parses_faces_and_groups() {
+        let obj = ObjFile::parse(QUAD).unwrap();
+        assert_eq!(obj.material_libs, vec!["scene.mtl"]);
+        assert_eq!(obj.groups.len(), 2);
+
+        let floor = &obj.groups[0];
+        assert_eq!(floor.name, "floor");
+        assert_eq!(floor.material, Some("red".to_string()));
+        assert_eq!(floor.triangles.len(), 2);
+        let fan: Vec<_> = floor.triangles[1].iter().map(|c| c.position).collect();
+        assert_eq!(fan, vec![0, 2, 3]);
+
+        let lamp = &obj.groups[1];
+        assert_eq!(lamp.name, "lamp");
+        let corners: Vec<_> = lamp.triangles[0].iter().map(|c| c.position).collect();
+        assert_eq!(corners, vec![0, 2, 3]);
+        assert_eq!(lamp.triangles[0][0].uv, None);
+        assert_eq!(lamp.triangles[0][0].normal, Some(0));
+    }
+
+    #[test]
+    fn builds_shared_meshes() {
+        let obj = ObjFile::parse(QUAD).unwrap();
+        let default = grey();
+        let meshes = obj.build(&HashMap::new(), &default);
+        assert_eq!(meshes[0].data.positions.len(), 4);
+        assert_eq!(meshes[0].data.uvs.len(), 4);
+
+        let r = Ray::new(vec3(0.25, 0.75, 1.0), vec3(0, 0, -1), 0.0);
+        let rec = meshes[0].hit(&r, 0.001, f64::MAX).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn reports_bad_indices() {
+        match ObjFile::parse("v 0 0 0\nv 1 0 0\nf 1 2 3\n") {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 3),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn maps_mtl_materials() {
+        let mtl = parse_mtl(
+            "newmtl red\nKd 0.8 0.1 0.1\n\
+             newmtl plastic\nKd 0.8 0.1 0.1\nKs 0.5 0.5 0.5\nNs 98\n\
+             newmtl chrome\nKd 0 0 0\nKs 0.9 0.9 0.9\nNs 98\n\
+             newmtl mirror\nKd 0.8 0.1 0.1\nKs 0.9 0.9 0.9\nNs 98\nillum 3\n\
+             newmtl glass\nNi 1.5\nd 0.2\n\
+             newmtl light\nKe 10 10 10\n",
+        )
+        .unwrap();
+        assert_eq!(mtl.len(), 6);
+        assert_eq!(mtl["red"].kd, vec3(0.8, 0.1, 0.1));
+        assert!((mtl["chrome"].ns - 98.0).abs() < 1e-12);
+        assert_eq!(mtl["mirror"].illum, 3);
+
+        // light arrives at 45 degrees onto a floor facing +y
+        let r_in = Ray::new(vec3(-1, 1, 0), vec3(1, -1, 0), 0.0);
+        let probe = |name: &str| {
+            let material = mtl[name].build();
+            let rec = HitRecord {
+                t: 1.0,
+                p: vec3(0, 0, 0),
+                normal: vec3(0, 1, 0),
+                u: 0.0,
+                v: 0.0,
+                tangent: None,
+                color: None,
+                material: Arc::clone(&material),
+            };
+            (material, rec)
+        };
+        let mirror_direction = vec3(1, 1, 0);
+
+        // diffuse surfaces reflect kd / pi in every direction, and a specular
+        // colour alone doesn't make them shiny
+        for name in &["red", "plastic"] {
+            let (m, rec) = probe(name);
+            let f = m.eval(&r_in, &rec, vec3(0, 1, 0)).unwrap();
+            assert!((f * f64::consts::PI - vec3(0.8, 0.1, 0.1)).length() < 1e-9);
+        }
+
+        // Ns 98 is a fuzz ball of radius^2 0.02, which peaks at
+        // ks * (3 + 0.02) / (2 pi 0.02) along the mirror
+        for name in &["chrome", "mirror"] {
+            let (m, rec) = probe(name);
+            let f = m.eval(&r_in, &rec, mirror_direction).unwrap();
+            let peak = 0.9 * 3.02 / (2.0 * f64::consts::PI * 0.02);
+            assert!((f - vec3(peak, peak, peak)).length() < 1e-9);
+        }
+
+        let (glass, rec) = probe("glass");
+        assert!(glass.eval(&r_in, &rec, mirror_direction).is_none());
+        assert!(glass.scatter(r_in.clone(), rec).unwrap().is_specular);
+
+        let (light, rec) = probe("light");
+        assert_eq!(light.emitted(&r_in, &rec), vec3(10, 10, 10));
+        assert!(light.scatter(r_in.clone(), rec).is_none());
+    }
+}
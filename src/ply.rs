@@ -0,0 +1,535 @@
+//!
+//! PLY mesh import, for ASCII and little/big endian binary files.
+//!
+//! Vertex positions, normals, texture coordinates and colours are picked out
+//! by property name, in whatever order the header declares them; any other
+//! properties and elements are read and discarded. Polygons are fan
+//! triangulated.
+use material::Material;
+use mesh::{MeshData, TriangleMesh};
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str;
+use std::sync::Arc;
+use vec3::vec3;
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io(io::Error),
+    Format(String),
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PlyError::Io(ref e) => write!(f, "{}", e),
+            PlyError::Format(ref msg) => write!(f, "invalid ply file: {}", msg),
+        }
+    }
+}
+
+impl error::Error for PlyError {}
+
+impl From<io::Error> for PlyError {
+    fn from(e: io::Error) -> PlyError {
+        PlyError::Io(e)
+    }
+}
+
+fn format_error<T, S: Into<String>>(msg: S) -> Result<T, PlyError> {
+    Err(PlyError::Format(msg.into()))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, PlyError> {
+        Ok(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return format_error(format!("unknown property type '{}'", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    /// The value that maps to full intensity for a colour channel.
+    fn color_scale(self) -> f64 {
+        match self {
+            ScalarType::U8 | ScalarType::I8 => 255.0,
+            ScalarType::U16 | ScalarType::I16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Property {
+    Scalar(String, ScalarType),
+    List(String, ScalarType, ScalarType),
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match *self {
+            Property::Scalar(ref name, _) | Property::List(ref name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Header {
+    encoding: Encoding,
+    elements: Vec<Element>,
+}
+
+/// Parses the header and returns it with the offset of the body.
+fn parse_header(bytes: &[u8]) -> Result<(Header, usize), PlyError> {
+    let mut encoding = None;
+    let mut elements: Vec<Element> = vec![];
+    let mut pos = 0;
+
+    for line_no in 0.. {
+        let end = match bytes[pos..].iter().position(|&b| b == b'\n') {
+            Some(len) => pos + len,
+            None => return format_error("missing end_header"),
+        };
+        let line = match str::from_utf8(&bytes[pos..end]) {
+            Ok(line) => line.trim(),
+            Err(_) => return format_error("header is not text"),
+        };
+        pos = end + 1;
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if line_no == 0 {
+            if line != "ply" {
+                return format_error("missing ply magic");
+            }
+            continue;
+        }
+        match words.as_slice() {
+            ["format", format, _] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::LittleEndian,
+                    "binary_big_endian" => Encoding::BigEndian,
+                    _ => return format_error(format!("unknown format '{}'", format)),
+                })
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => (),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: match count.parse() {
+                    Ok(count) => count,
+                    Err(_) => return format_error(format!("invalid element count '{}'", count)),
+                },
+                properties: vec![],
+            }),
+            ["property", ..] => {
+                let property = match words[1..] {
+                    ["list", count_type, item_type, name] => Property::List(
+                        name.to_string(),
+                        ScalarType::parse(count_type)?,
+                        ScalarType::parse(item_type)?,
+                    ),
+                    [ty, name] => Property::Scalar(name.to_string(), ScalarType::parse(ty)?),
+                    _ => return format_error(format!("invalid property '{}'", line)),
+                };
+                match elements.last_mut() {
+                    Some(e) => e.properties.push(property),
+                    None => return format_error("property before element"),
+                }
+            }
+            ["end_header"] => break,
+            _ => return format_error(format!("unexpected header line '{}'", line)),
+        }
+    }
+
+    match encoding {
+        Some(encoding) => Ok((Header { encoding, elements }, pos)),
+        None => format_error("missing format line"),
+    }
+}
+
+/// Reads scalar values from the body in either encoding.
+struct BodyReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    encoding: Encoding,
+}
+
+impl<'a> BodyReader<'a> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64, PlyError> {
+        if self.encoding == Encoding::Ascii {
+            return self.read_ascii();
+        }
+
+        let n = ty.size();
+        if self.pos + n > self.bytes.len() {
+            return format_error("unexpected end of data");
+        }
+        let mut b = [0u8; 8];
+        b[..n].copy_from_slice(&self.bytes[self.pos..self.pos + n]);
+        self.pos += n;
+        if self.encoding == Encoding::BigEndian {
+            b[..n].reverse();
+        }
+
+        Ok(match ty {
+            ScalarType::I8 => f64::from(b[0] as i8),
+            ScalarType::U8 => f64::from(b[0]),
+            ScalarType::I16 => f64::from(i16::from_le_bytes([b[0], b[1]])),
+            ScalarType::U16 => f64::from(u16::from_le_bytes([b[0], b[1]])),
+            ScalarType::I32 => f64::from(i32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            ScalarType::U32 => f64::from(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            ScalarType::F32 => f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            ScalarType::F64 => f64::from_le_bytes(b),
+        })
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, PlyError> {
+        let rest = &self.bytes[self.pos..];
+        let start = match rest.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(start) => start,
+            None => return format_error("unexpected end of data"),
+        };
+        let len = rest[start..]
+            .iter()
+            .position(|b| b.is_ascii_whitespace())
+            .unwrap_or(rest.len() - start);
+        self.pos += start + len;
+
+        let word = str::from_utf8(&rest[start..start + len]).unwrap_or("");
+        match word.parse() {
+            Ok(value) => Ok(value),
+            Err(_) => format_error(format!("invalid number '{}'", word)),
+        }
+    }
+
+    fn read_list(
+        &mut self,
+        count_type: ScalarType,
+        item_type: ScalarType,
+    ) -> Result<Vec<f64>, PlyError> {
+        let count = self.read(count_type)?;
+        if count < 0.0 {
+            return format_error("negative list length");
+        }
+        (0..count as usize).map(|_| self.read(item_type)).collect()
+    }
+}
+
+/// Where a vertex property ends up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Slot {
+    Position(usize),
+    Normal(usize),
+    Uv(usize),
+    Color(usize, f64),
+    Ignored,
+}
+
+impl Slot {
+    fn for_property(property: &Property) -> Slot {
+        let ty = match *property {
+            Property::Scalar(_, ty) => ty,
+            Property::List(..) => return Slot::Ignored,
+        };
+        match property.name() {
+            "x" => Slot::Position(0),
+            "y" => Slot::Position(1),
+            "z" => Slot::Position(2),
+            "nx" => Slot::Normal(0),
+            "ny" => Slot::Normal(1),
+            "nz" => Slot::Normal(2),
+            "u" | "s" | "texture_u" | "texture_s" => Slot::Uv(0),
+            "v" | "t" | "texture_v" | "texture_t" => Slot::Uv(1),
+            "red" | "r" | "diffuse_red" => Slot::Color(0, ty.color_scale()),
+            "green" | "g" | "diffuse_green" => Slot::Color(1, ty.color_scale()),
+            "blue" | "b" | "diffuse_blue" => Slot::Color(2, ty.color_scale()),
+            _ => Slot::Ignored,
+        }
+    }
+}
+
+/// Whether all of `slots` are fed by some property.
+fn has_all<F: Fn(usize) -> Slot>(slots: &[Slot], n: usize, slot: F) -> bool {
+    (0..n).all(|i| {
+        slots.iter().any(|s| match (*s, slot(i)) {
+            (Slot::Color(a, _), Slot::Color(b, _)) => a == b,
+            (a, b) => a == b,
+        })
+    })
+}
+
+/// Parses a PLY file into mesh buffers. Normals, texture coordinates and
+/// vertex colours are kept when the file provides them.
+pub fn parse_ply(bytes: &[u8], material: Arc<dyn Material>) -> Result<MeshData, PlyError> {
+    let (header, body) = parse_header(bytes)?;
+    let mut reader = BodyReader {
+        bytes,
+        pos: body,
+        encoding: header.encoding,
+    };
+
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut colors = vec![];
+    let mut indices: Vec<[u32; 3]> = vec![];
+    let mut has_vertices = false;
+    let (mut has_normals, mut has_uvs, mut has_colors) = (false, false, false);
+
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let slots: Vec<Slot> = element.properties.iter().map(Slot::for_property).collect();
+                if !has_all(&slots, 3, Slot::Position) {
+                    return format_error("vertex element without x, y and z");
+                }
+                has_vertices = true;
+                has_normals = has_all(&slots, 3, Slot::Normal);
+                has_uvs = has_all(&slots, 2, Slot::Uv);
+                has_colors = has_all(&slots, 3, |i| Slot::Color(i, 1.0));
+
+                for _ in 0..element.count {
+                    let (mut p, mut n, mut uv, mut c) = ([0.0; 3], [0.0; 3], [0.0; 2], [0.0; 3]);
+                    for (property, slot) in element.properties.iter().zip(&slots) {
+                        let value = match *property {
+                            Property::Scalar(_, ty) => reader.read(ty)?,
+                            Property::List(_, count_type, item_type) => {
+                                reader.read_list(count_type, item_type)?;
+                                continue;
+                            }
+                        };
+                        match *slot {
+                            Slot::Position(i) => p[i] = value,
+                            Slot::Normal(i) => n[i] = value,
+                            Slot::Uv(i) => uv[i] = value,
+                            Slot::Color(i, scale) => c[i] = value / scale,
+                            Slot::Ignored => (),
+                        }
+                    }
+                    positions.push(vec3(p[0], p[1], p[2]));
+                    normals.push(vec3(n[0], n[1], n[2]));
+                    uvs.push((uv[0], uv[1]));
+                    colors.push(vec3(c[0], c[1], c[2]));
+                }
+            }
+            "face" => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        match *property {
+                            Property::List(ref name, count_type, item_type)
+                                if name == "vertex_indices" || name == "vertex_index" =>
+                            {
+                                let face = reader.read_list(count_type, item_type)?;
+                                if face.len() < 3 {
+                                    return format_error("a face needs at least 3 vertices");
+                                }
+                                if face.iter().any(|&i| i < 0.0) {
+                                    return format_error("negative face index");
+                                }
+                                for i in 1..face.len() - 1 {
+                                    indices.push([
+                                        face[0] as u32,
+                                        face[i] as u32,
+                                        face[i + 1] as u32,
+                                    ]);
+                                }
+                            }
+                            Property::List(_, count_type, item_type) => {
+                                reader.read_list(count_type, item_type)?;
+                            }
+                            Property::Scalar(_, ty) => {
+                                reader.read(ty)?;
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        match *property {
+                            Property::List(_, count_type, item_type) => {
+                                reader.read_list(count_type, item_type)?;
+                            }
+                            Property::Scalar(_, ty) => {
+                                reader.read(ty)?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if !has_vertices {
+        return format_error("no vertex element");
+    }
+    if indices
+        .iter()
+        .any(|tri| tri.iter().any(|&i| i as usize >= positions.len()))
+    {
+        return format_error("face index out of range");
+    }
+
+    let mut data = MeshData::new(positions, indices, material);
+    if has_normals {
+        data = data.with_normals(normals);
+    }
+    if has_uvs {
+        data = data.with_uvs(uvs);
+    }
+    if has_colors {
+        data = data.with_colors(colors);
+    }
+    Ok(data)
+}
+
+/// Loads a PLY file as a mesh with its own BVH.
+pub fn load_ply<P: AsRef<Path>>(
+    path: P,
+    material: Arc<dyn Material>,
+) -> Result<TriangleMesh, PlyError> {
+    let bytes = fs::read(path)?;
+    Ok(TriangleMesh::new(parse_ply(&bytes, material)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::tests::grey;
+    use geo::Hittable;
+    use std::f64;
+    use vec3::Ray;
+
+    const HEADER: &str = "element vertex 4
+property uchar red
+property float x
+property float z
+property uchar green
+property float y
+property uchar blue
+element face 1
+property uchar flags
+property list uchar int vertex_indices
+end_header
+";
+
+    fn binary_quad(format: &str, big_endian: bool) -> Vec<u8> {
+        let mut bytes =
+            format!("ply\nformat {} 1.0\ncomment test\n{}", format, HEADER).into_bytes();
+        let corners = [(0.0f32, 0.0f32), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        for &(x, y) in &corners {
+            let float = |v: f32| {
+                if big_endian {
+                    v.to_be_bytes()
+                } else {
+                    v.to_le_bytes()
+                }
+            };
+            bytes.push(255);
+            bytes.extend_from_slice(&float(x));
+            bytes.extend_from_slice(&float(0.0));
+            bytes.push(0);
+            bytes.extend_from_slice(&float(y));
+            bytes.push(51);
+        }
+        bytes.extend_from_slice(&[7, 4]);
+        for i in 0..4i32 {
+            let i = if big_endian {
+                i.to_be_bytes()
+            } else {
+                i.to_le_bytes()
+            };
+            bytes.extend_from_slice(&i);
+        }
+        bytes
+    }
+
+    fn check_quad(data: &MeshData) {
+        assert_eq!(data.positions[2], vec3(1, 1, 0));
+        assert_eq!(data.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(data.colors[3], vec3(1.0, 0.0, 0.2));
+        assert!(data.normals.is_empty() && data.uvs.is_empty());
+    }
+
+    #[test]
+    fn reads_ascii() {
+        let src = format!(
+            "ply\nformat ascii 1.0\n{}255 0 0 0 0 51\n255 1 0 0 0 51\n255 1 0 0 1 51\n255 0 0 0 1 51\n7 4 0 1 2 3\n",
+            HEADER
+        );
+        check_quad(&parse_ply(src.as_bytes(), grey()).unwrap());
+    }
+
+    #[test]
+    fn reads_binary() {
+        let le = parse_ply(&binary_quad("binary_little_endian", false), grey()).unwrap();
+        check_quad(&le);
+        let be = parse_ply(&binary_quad("binary_big_endian", true), grey()).unwrap();
+        check_quad(&be);
+    }
+
+    #[test]
+    fn vertex_colors_drive_albedo() {
+        let mesh = TriangleMesh::new(
+            parse_ply(&binary_quad("binary_little_endian", false), grey()).unwrap(),
+        );
+        let r = Ray::new(vec3(0.5, 0.5, 1.0), vec3(0, 0, -1), 0.0);
+        let rec = mesh.hit(&r, 0.001, f64::MAX).unwrap();
+        // straight back along the normal a Lambertian reflects albedo / pi
+        let f = rec.material.eval(&r, &rec, rec.normal).unwrap();
+        assert!((f * f64::consts::PI - vec3(1.0, 0.0, 0.2)).length() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let mut bytes = binary_quad("binary_little_endian", false);
+        bytes.truncate(bytes.len() - 2);
+        assert!(parse_ply(&bytes, grey()).is_err());
+        assert!(parse_ply(b"ply\nformat ascii 1.0\n", grey()).is_err());
+    }
+}
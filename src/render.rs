@@ -0,0 +1,451 @@
+//!
+//! Turns a world and a camera into a framebuffer of linear colour values
+use background::Background;
+use camera::Camera;
+use geo::{HitRecord, Hittable, HittableList};
+use material::power_heuristic;
+use rand::prelude::*;
+use rayon::prelude::*;
+use scene::Scene;
+use std::f64;
+use vec3::{vec3, Ray, Vec3};
+
+pub use film::Framebuffer;
+
+/// How the radiance along a path is estimated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Integrator {
+    /// Follow the material's scattered ray only.
+    Path,
+    /// Also sample the scene's lights with a shadow ray at every diffuse bounce.
+    LightSampling,
+    /// Combine light and BSDF sampling with the power heuristic.
+    Mis,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    /// Hard limit on the number of bounces, as a safety net.
+    pub max_depth: u32,
+    /// Bounces after which paths are terminated by Russian roulette.
+    pub roulette_depth: u32,
+    pub integrator: Integrator,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            width: 800,
+            height: 400,
+            samples: 10,
+            max_depth: 50,
+            roulette_depth: 5,
+            integrator: Integrator::Path,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn aspect(&self) -> f64 {
+        f64::from(self.width) / f64::from(self.height)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Renderer {
+    pub settings: RenderSettings,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Renderer {
+        Renderer { settings }
+    }
+
+    pub fn render_scene(&self, scene: &Scene) -> Framebuffer {
+        let tracer = Tracer {
+            world: &*scene.world,
+            lights: &scene.lights,
+            background: &*scene.background,
+            integrator: self.settings.integrator,
+            max_depth: self.settings.max_depth,
+            roulette_depth: self.settings.roulette_depth,
+        };
+        self.render(&tracer, &scene.camera)
+    }
+
+    pub fn render<T: Hittable + ?Sized>(&self, tracer: &Tracer<T>, cam: &Camera) -> Framebuffer {
+        let RenderSettings {
+            width: nx,
+            height: ny,
+            samples: ns,
+            ..
+        } = self.settings;
+        let mut fb = Framebuffer::new(nx, ny);
+
+        fb.pixels
+            .par_chunks_mut(nx as usize)
+            .rev()
+            .enumerate()
+            .for_each(|(j, row)| {
+                let mut rng = thread_rng();
+                for (i, pixel) in row.iter_mut().enumerate() {
+                    let mut total_color = vec3(0.0, 0.0, 0.0);
+
+                    for _ in 0..ns {
+                        let a: f64 = rng.gen();
+                        let b: f64 = rng.gen();
+
+                        let u = (i as f64 + a) / f64::from(nx);
+                        let v = (j as f64 + b) / f64::from(ny);
+                        let r = cam.get_ray(u, v);
+                        total_color = total_color + tracer.color(&r);
+                    }
+
+                    *pixel = Framebuffer::to_pixel(total_color / f64::from(ns));
+                }
+            });
+
+        fb
+    }
+}
+
+/// Estimates the radiance arriving along camera rays.
+#[derive(Debug)]
+pub struct Tracer<'a, T: Hittable + ?Sized + 'a> {
+    pub world: &'a T,
+    /// Emitters to sample directly, all of them `is_sampleable`. Emissive
+    /// objects left out are still found by the scattered rays.
+    pub lights: &'a HittableList,
+    pub background: &'a dyn Background,
+    pub integrator: Integrator,
+    pub max_depth: u32,
+    pub roulette_depth: u32,
+}
+
+impl<'a, T: Hittable + ?Sized> Tracer<'a, T> {
+    pub fn color(&self, r: &Ray) -> Vec3 {
+        let mut rng = thread_rng();
+        let mut radiance = vec3(0.0, 0.0, 0.0);
+        let mut throughput = vec3(1.0, 1.0, 1.0);
+        let mut ray = r.clone();
+        // scales light emitted by the next surface hit, so that light already
+        // counted by light sampling isn't counted twice
+        let mut emission_weight = 1.0;
+        let sample_lights = self.integrator != Integrator::Path && !self.lights.list.is_empty();
+
+        for depth in 0.. {
+            let rec = match self.world.hit(&ray, 0.001, f64::MAX) {
+                Some(rec) => rec,
+                None => {
+                    radiance = radiance + throughput * self.background.color(&ray);
+                    break;
+                }
+            };
+
+            if emission_weight > 0.0 {
+                radiance =
+                    radiance + emission_weight * throughput * rec.material.emitted(&ray, &rec);
+            }
+            if depth >= self.max_depth {
+                break;
+            }
+
+            let h = match rec.material.scatter(ray.clone(), rec.clone()) {
+                Some(h) => h,
+                None => break,
+            };
+
+            emission_weight = if h.is_specular || !sample_lights {
+                1.0
+            } else {
+                radiance = radiance + throughput * self.direct_light(&ray, &rec);
+                let light_pdf = self.lights.pdf_value(rec.p, h.scattered.direction());
+                match self.integrator {
+                    Integrator::Mis => power_heuristic(h.pdf, light_pdf),
+                    // emitters the lights can't be sampled towards are only
+                    // reached by this ray, so they keep their full weight
+                    _ if light_pdf > 0.0 => 0.0,
+                    _ => 1.0,
+                }
+            };
+
+            throughput = throughput * h.attenuation;
+            if depth + 1 >= self.roulette_depth {
+                // continue with a probability that follows the throughput and
+                // boost the survivors so the estimate stays unbiased
+                let survival = throughput.max_component().min(1.0);
+                if survival <= 0.0 || rng.gen::<f64>() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+            ray = h.scattered;
+        }
+
+        radiance
+    }
+
+    /// Samples a direction towards the lights and traces a shadow ray along it.
+    fn direct_light(&self, r: &Ray, rec: &HitRecord) -> Vec3 {
+        let black = vec3(0.0, 0.0, 0.0);
+        let direction = self.lights.random_direction(rec.p);
+        let pdf = self.lights.pdf_value(rec.p, direction);
+        if pdf <= 0.0 {
+            return black;
+        }
+
+        let f = match rec.material.eval(r, rec, direction) {
+            Some(f) => f,
+            None => return black,
+        };
+
+        let weight = match self.integrator {
+            Integrator::Mis => power_heuristic(pdf, rec.material.scattering_pdf(r, rec, direction)),
+            _ => 1.0,
+        };
+
+        let shadow = Ray::new(rec.p, direction, r.time());
+        match self.world.hit(&shadow, 0.001, f64::MAX) {
+            Some(light_rec) => weight * f * light_rec.material.emitted(&shadow, &light_rec) / pdf,
+            None => black,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use background::{Constant, Gradient};
+    use geo::tests::grey;
+    use geo::Sphere;
+    use material::{DiffuseLight, Lambertian, Material, MaterialReflection};
+    use scenefile::SceneDescription;
+    use std::sync::Arc;
+    use tonemap::ToneMapper;
+
+    fn black() -> Constant {
+        Constant {
+            color: vec3(0, 0, 0),
+        }
+    }
+
+    #[test]
+    fn empty_world_renders_sky() {
+        let settings = RenderSettings {
+            width: 4,
+            height: 2,
+            samples: 1,
+            ..RenderSettings::default()
+        };
+        let cam = Camera::new(
+            vec3(0, 0, 0),
+            vec3(0, 0, -1),
+            vec3(0, 1, 0),
+            90.0,
+            settings.aspect(),
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        );
+        let world = HittableList { list: vec![] };
+        let tracer = Tracer {
+            world: &world,
+            lights: &HittableList { list: vec![] },
+            background: &Gradient::default(),
+            integrator: Integrator::Path,
+            max_depth: 5,
+            roulette_depth: 5,
+        };
+        let fb = Renderer::new(settings).render(&tracer, &cam);
+
+        assert_eq!(fb.pixels.len(), 8);
+        assert_eq!(fb.to_rgb8(&ToneMapper::default()).len(), 24);
+        // the sky gets bluer towards the top of the image
+        assert!(fb.get(0, 0).x() < fb.get(0, 1).x());
+    }
+
+    #[test]
+    fn emitted_light_is_added_at_the_hit() {
+        let light = Sphere::new(
+            vec3(0, 0, -2),
+            1.0,
+            Arc::new(DiffuseLight {
+                emit: vec3(4, 4, 4),
+            }),
+        );
+        let r = Ray::new(vec3(0, 0, 0), vec3(0, 0, -1), 0.0);
+        let tracer = Tracer {
+            world: &light,
+            lights: &HittableList { list: vec![] },
+            background: &black(),
+            integrator: Integrator::Path,
+            max_depth: 5,
+            roulette_depth: 5,
+        };
+        assert_eq!(tracer.color(&r), vec3(4, 4, 4));
+    }
+
+    fn lit_floor_estimate(integrator: Integrator, n: u32) -> f64 {
+        // a diffuse floor lit by a sphere light straight above the hit point
+        let light = Sphere::new(
+            vec3(0, 3, 0),
+            1.0,
+            Arc::new(DiffuseLight {
+                emit: vec3(4, 4, 4),
+            }),
+        );
+        let world = HittableList {
+            list: vec![
+                Box::new(Sphere::new(vec3(0, -1000, 0), 1000.0, grey())),
+                Box::new(light.clone()),
+            ],
+        };
+        let lights = HittableList {
+            list: vec![Box::new(light)],
+        };
+        let tracer = Tracer {
+            world: &world,
+            lights: &lights,
+            background: &black(),
+            integrator,
+            max_depth: 1,
+            roulette_depth: 1,
+        };
+        floor_estimate(&tracer, n)
+    }
+
+    /// The mean radiance seen at the origin of the floor, one bounce deep.
+    fn floor_estimate<T: Hittable + ?Sized>(tracer: &Tracer<T>, n: u32) -> f64 {
+        floor_samples(tracer, n).0
+    }
+
+    /// The mean radiance at the floor origin with its standard error.
+    fn floor_samples<T: Hittable + ?Sized>(tracer: &Tracer<T>, n: u32) -> (f64, f64) {
+        let r = Ray::new(vec3(0.0, 0.5, 1.0), vec3(0.0, -0.5, -1.0), 0.0);
+        let (mut sum, mut sum_squares) = (0.0, 0.0);
+        for _ in 0..n {
+            let x = tracer.color(&r).x();
+            sum += x;
+            sum_squares += x * x;
+        }
+        let n = f64::from(n);
+        let mean = sum / n;
+        let variance = (sum_squares / n - mean * mean).max(0.0);
+        (mean, (variance / n).sqrt())
+    }
+
+    /// Diffuse and emissive at once, for furnace tests.
+    #[derive(Debug)]
+    struct GlowingLambertian {
+        albedo: Vec3,
+        emit: Vec3,
+    }
+
+    impl Material for GlowingLambertian {
+        fn scatter(&self, r_in: Ray, rec: HitRecord) -> Option<MaterialReflection> {
+            Lambertian {
+                albedo: self.albedo,
+            }
+            .scatter(r_in, rec)
+        }
+
+        fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Vec3 {
+            self.emit
+        }
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        // inside a closed glowing sphere radiance is emit / (1 - albedo); the
+        // negative radius turns the normals inwards
+        let world = Sphere::new(
+            vec3(0, 0, 0),
+            -10.0,
+            Arc::new(GlowingLambertian {
+                albedo: vec3(0.5, 0.5, 0.5),
+                emit: vec3(1, 1, 1),
+            }),
+        );
+        let tracer = Tracer {
+            world: &world,
+            lights: &HittableList { list: vec![] },
+            background: &black(),
+            integrator: Integrator::Path,
+            max_depth: 1000,
+            roulette_depth: 2,
+        };
+
+        let r = Ray::new(vec3(0, 0, 0), vec3(0, 0, -1), 0.0);
+        let n = 4000;
+        let mut total = vec3(0, 0, 0);
+        for _ in 0..n {
+            total = total + tracer.color(&r);
+        }
+        let estimate = total.x() / f64::from(n);
+        assert!((estimate - 2.0).abs() < 0.1);
+    }
+
+    // albedo * radiance * (radius / distance)^2
+    const LIT_FLOOR: f64 = 0.5 * 4.0 / 9.0;
+
+    #[test]
+    fn light_sampling_matches_analytic_irradiance() {
+        let estimate = lit_floor_estimate(Integrator::LightSampling, 2000);
+        assert!((estimate - LIT_FLOOR).abs() < 0.02 * LIT_FLOOR);
+    }
+
+    #[test]
+    fn mis_matches_analytic_irradiance() {
+        let estimate = lit_floor_estimate(Integrator::Mis, 4000);
+        assert!((estimate - LIT_FLOOR).abs() < 0.03 * LIT_FLOOR);
+    }
+
+    #[test]
+    fn unsampleable_emitters_are_not_dropped() {
+        // a moving sphere can't be sampled as a light, so only the sphere
+        // lamp goes in the light list; the moving one adds
+        // albedo * radiance * (radius / distance)^2 * cos = 0.5 * 4 / 25 * 0.8
+        let scene = SceneDescription::parse(
+            "
+background constant color=0,0,0
+material ground lambertian albedo=0.5,0.5,0.5
+material lamp diffuse_light emit=4,4,4
+sphere center=0,-1000,0 radius=1000 material=ground
+sphere center=0,3,0 radius=1 material=lamp
+moving_sphere center0=3,4,0 center1=3,4,0 radius=1 material=lamp
+",
+        )
+        .unwrap()
+        .build(1.0)
+        .unwrap();
+        assert_eq!(scene.lights.list.len(), 1);
+        let expected = LIT_FLOOR + 0.064;
+
+        for &(integrator, n) in &[
+            (Integrator::Path, 40000),
+            (Integrator::LightSampling, 20000),
+        ] {
+            let tracer = Tracer {
+                world: &*scene.world,
+                lights: &scene.lights,
+                background: &*scene.background,
+                integrator,
+                max_depth: 1,
+                roulette_depth: 1,
+            };
+            // six standard errors make a spurious failure vanishingly rare
+            let (estimate, error) = floor_samples(&tracer, n);
+            assert!(
+                (estimate - expected).abs() < 6.0 * error,
+                "{:?} gave {} +- {}",
+                integrator,
+                estimate,
+                error
+            );
+        }
+    }
+}
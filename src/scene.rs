@@ -1,19 +1,40 @@
 //!
 //! Contains helpers for constructing scenes out of geometry primitives
-use geo::{BVHNode, Hittable, HittableList, MovingSphere, Sphere};
-use material::{Dielectric, Lambertian, Metal};
+use background::Background;
+use bvh::LinearBvh;
+use camera::Camera;
+use geo::{BvhBuilder, Hittable, HittableList};
 use rand::prelude::*;
-use std::sync::Arc;
+use scenefile::{
+    BackgroundDesc, CameraDesc, MaterialDesc, ObjectDesc, RectPlane, SceneDescription,
+};
 use vec3::vec3;
 
-pub fn random_scene() -> HittableList {
-    let mut list: Vec<Box<Hittable>> = vec![Box::new(Sphere {
+/// A world ready for rendering, together with the camera that looks at it.
+#[derive(Debug)]
+pub struct Scene {
+    pub world: Box<dyn Hittable>,
+    pub camera: Camera,
+    pub background: Box<dyn Background>,
+    /// The emissive objects of `world`, sampled directly by the integrator.
+    pub lights: HittableList,
+}
+
+pub fn random_scene_description() -> SceneDescription {
+    let mut scene = SceneDescription::default();
+    let ground = scene.add_material(
+        "ground",
+        MaterialDesc::Lambertian {
+            albedo: vec3(0.5, 0.5, 0.5),
+        },
+    );
+    scene.objects.push(ObjectDesc::Sphere {
         center: vec3(0, -1000, 0),
         radius: 1000.0,
-        material: Arc::new(Lambertian {
-            albedo: vec3(0.5, 0.5, 0.5),
-        }),
-    })];
+        material: ground,
+    });
+
+    let glass = scene.add_material("glass", MaterialDesc::Dielectric { ref_idx: 1.5 });
 
     let mut rng = thread_rng();
     for a in -10..10 {
@@ -22,6 +43,7 @@ pub fn random_scene() -> HittableList {
             let v1: f64 = rng.gen();
             let v2: f64 = rng.gen();
             let center = vec3(a as f64 + 0.9 * v1, 0.2, b as f64 + 0.9 * v2);
+            let name = format!("m{}_{}", a + 10, b + 10);
 
             if (center - vec3(4.0, 0.2, 0.0)).length() > 0.9 {
                 match choose_mat {
@@ -31,16 +53,20 @@ pub fn random_scene() -> HittableList {
                         let a1: f64 = rng.gen();
                         let a2: f64 = rng.gen();
                         let a3: f64 = rng.gen();
-                        list.push(Box::new(MovingSphere::new(
-                            center,
-                            center + vec3(0.0, 0.5 * v3, 0.0),
-                            0.0,
-                            1.0,
-                            0.2,
-                            Arc::new(Lambertian {
+                        let material = scene.add_material(
+                            name,
+                            MaterialDesc::Lambertian {
                                 albedo: vec3(a1, a2, a3),
-                            }),
-                        )));
+                            },
+                        );
+                        scene.objects.push(ObjectDesc::MovingSphere {
+                            center0: center,
+                            center1: center + vec3(0.0, 0.5 * v3, 0.0),
+                            time0: 0.0,
+                            time1: 1.0,
+                            radius: 0.2,
+                            material,
+                        });
                     }
 
                     x if x < 0.95 => {
@@ -49,149 +75,263 @@ pub fn random_scene() -> HittableList {
                         let a2: f64 = rng.gen();
                         let a3: f64 = rng.gen();
                         let a4: f64 = rng.gen();
-                        list.push(Box::new(Sphere::new(
-                            center,
-                            0.2,
-                            Arc::new(Metal {
+                        let material = scene.add_material(
+                            name,
+                            MaterialDesc::Metal {
                                 albedo: vec3(0.5 * 1.0 + a1, 0.5 * 1.0 + a2, 0.5 * 1.0 + a3),
                                 fuzz: 0.5 * 1.0 + a4,
-                            }),
-                        )));
+                            },
+                        );
+                        scene.objects.push(ObjectDesc::Sphere {
+                            center,
+                            radius: 0.2,
+                            material,
+                        });
                     }
 
                     _ => {
                         // glass
-                        list.push(Box::new(Sphere::new(
+                        scene.objects.push(ObjectDesc::Sphere {
                             center,
-                            0.2,
-                            Arc::new(Dielectric { ref_idx: 1.5 }),
-                        )));
+                            radius: 0.2,
+                            material: glass.clone(),
+                        });
                     }
                 }
             }
         }
     }
 
-    list.append(&mut vec![
-        Box::new(Sphere {
+    let brown = scene.add_material(
+        "brown",
+        MaterialDesc::Lambertian {
+            albedo: vec3(0.4, 0.2, 0.1),
+        },
+    );
+    let gold = scene.add_material(
+        "gold",
+        MaterialDesc::Metal {
+            fuzz: 0.0,
+            albedo: vec3(0.8, 0.6, 0.2),
+        },
+    );
+    scene.objects.append(&mut vec![
+        ObjectDesc::Sphere {
             center: vec3(0, 1, 0),
             radius: 1.0,
-            material: Arc::new(Dielectric { ref_idx: 1.5 }),
-        }),
-        Box::new(Sphere {
+            material: glass,
+        },
+        ObjectDesc::Sphere {
             center: vec3(-4, 1, 0),
             radius: 1.0,
-            material: Arc::new(Lambertian {
-                albedo: vec3(0.4, 0.2, 0.1),
-            }),
-        }),
-        Box::new(Sphere {
+            material: brown,
+        },
+        ObjectDesc::Sphere {
             center: vec3(4, 1, 0),
             radius: 1.0,
-            material: Arc::new(Metal {
-                fuzz: 0.0,
-                albedo: vec3(0.8, 0.6, 0.2),
-            }),
-        }),
+            material: gold,
+        },
     ]);
 
-    HittableList { list }
+    scene
 }
 
-pub fn simple_spheres() -> HittableList {
+pub fn random_scene() -> HittableList {
     HittableList {
-        list: vec![
-            Box::new(Sphere {
-                center: vec3(0, -1000, 0),
-                radius: 1000.0,
-                material: Arc::new(Lambertian {
-                    albedo: vec3(0.8, 0.8, 0.0),
-                }),
-            }),
-            Box::new(Sphere {
-                center: vec3(4, 1, 0),
-                radius: 1.0,
-                material: Arc::new(Lambertian {
-                    albedo: vec3(0.1, 0.2, 0.5),
-                }),
-            }),
-            Box::new(Sphere {
-                center: vec3(-4, 1, 0),
-                radius: 1.0,
-                material: Arc::new(Metal {
-                    fuzz: 0.0,
-                    albedo: vec3(0.8, 0.6, 0.2),
-                }),
-            }),
-            Box::new(Sphere {
-                center: vec3(0, 1, 0),
-                radius: 1.0,
-                material: Arc::new(Dielectric { ref_idx: 1.5 }),
-            }),
-            Box::new(Sphere {
-                center: vec3(0, 1, 0),
-                radius: -0.95,
-                material: Arc::new(Dielectric { ref_idx: 1.5 }),
-            }),
-            // Box::new(Sphere {
-            //     center: vec3(0, 1, 0),
-            //     radius: 1.0,
-            //     material: Arc::new(Dielectric { ref_idx: 1.5 }),
-            // }),
-            // Box::new(Sphere {
-            //     center: vec3(-4, 1, 0),
-            //     radius: 1.0,
-            //     material: Arc::new(Lambertian {
-            //         albedo: vec3(0.4, 0.2, 0.1),
-            //     }),
-            // }),
-            // Box::new(Sphere {
-            //     center: vec3(4, 1, 0),
-            //     radius: 1.0,
-            //     material: Arc::new(Metal {
-            //         fuzz: 0.0,
-            //         albedo: vec3(0.8, 0.6, 0.2),
-            //     }),
-            // }),
-        ],
+        list: random_scene_description().build_objects(),
     }
 }
 
-pub fn sphere_tree() -> BVHNode {
-    let v: Vec<Box<Hittable>> = vec![
-        Box::new(Sphere {
+pub fn simple_spheres_description() -> SceneDescription {
+    let mut scene = SceneDescription::default();
+    let ground = scene.add_material(
+        "ground",
+        MaterialDesc::Lambertian {
+            albedo: vec3(0.8, 0.8, 0.0),
+        },
+    );
+    let blue = scene.add_material(
+        "blue",
+        MaterialDesc::Lambertian {
+            albedo: vec3(0.1, 0.2, 0.5),
+        },
+    );
+    let gold = scene.add_material(
+        "gold",
+        MaterialDesc::Metal {
+            fuzz: 0.0,
+            albedo: vec3(0.8, 0.6, 0.2),
+        },
+    );
+    let glass = scene.add_material("glass", MaterialDesc::Dielectric { ref_idx: 1.5 });
+
+    scene.objects = vec![
+        ObjectDesc::Sphere {
             center: vec3(0, -1000, 0),
             radius: 1000.0,
-            material: Arc::new(Lambertian {
-                albedo: vec3(0.8, 0.8, 0.0),
-            }),
-        }),
-        Box::new(Sphere {
+            material: ground,
+        },
+        ObjectDesc::Sphere {
             center: vec3(4, 1, 0),
             radius: 1.0,
-            material: Arc::new(Lambertian {
-                albedo: vec3(0.1, 0.2, 0.5),
-            }),
-        }),
-        Box::new(Sphere {
+            material: blue,
+        },
+        ObjectDesc::Sphere {
             center: vec3(-4, 1, 0),
             radius: 1.0,
-            material: Arc::new(Metal {
-                fuzz: 0.0,
-                albedo: vec3(0.8, 0.6, 0.2),
-            }),
-        }),
-        Box::new(Sphere {
+            material: gold,
+        },
+        ObjectDesc::HollowSphere {
             center: vec3(0, 1, 0),
             radius: 1.0,
-            material: Arc::new(Dielectric { ref_idx: 1.5 }),
-        }),
-        Box::new(Sphere {
-            center: vec3(0, 1, 0),
-            radius: -0.95,
-            material: Arc::new(Dielectric { ref_idx: 1.5 }),
-        }),
+            thickness: 0.05,
+            material: glass,
+        },
     ];
 
-    BVHNode::new(v, 0.0, 1.0, &None)
+    scene
+}
+
+pub fn simple_spheres() -> HittableList {
+    HittableList {
+        list: simple_spheres_description().build_objects(),
+    }
+}
+
+pub fn sphere_tree_description() -> SceneDescription {
+    SceneDescription {
+        bvh: Some(BvhBuilder::default()),
+        ..simple_spheres_description()
+    }
+}
+
+pub fn sphere_tree() -> LinearBvh {
+    LinearBvh::new(
+        sphere_tree_description().build_objects(),
+        0.0,
+        1.0,
+        BvhBuilder::default(),
+    )
+}
+
+/// The simple spheres on a black background, lit only by a small sphere light.
+pub fn lit_spheres_description() -> SceneDescription {
+    let mut scene = sphere_tree_description();
+    scene.background = BackgroundDesc::Constant {
+        color: vec3(0, 0, 0),
+    };
+    let lamp = scene.add_material(
+        "lamp",
+        MaterialDesc::DiffuseLight {
+            emit: vec3(15, 15, 15),
+        },
+    );
+    scene.objects.push(ObjectDesc::Sphere {
+        center: vec3(2, 4, 3),
+        radius: 0.5,
+        material: lamp,
+    });
+    scene
+}
+
+/// The Cornell box: a white room with a red and a green wall, two boxes and
+/// a ceiling light.
+pub fn cornell_box_description() -> SceneDescription {
+    let mut scene = SceneDescription {
+        camera: CameraDesc {
+            look_from: vec3(278, 278, -800),
+            look_at: vec3(278, 278, 0),
+            vfov: 40.0,
+            aperture: 0.0,
+            ..CameraDesc::default()
+        },
+        bvh: Some(BvhBuilder::default()),
+        background: BackgroundDesc::Constant {
+            color: vec3(0, 0, 0),
+        },
+        ..SceneDescription::default()
+    };
+    let red = scene.add_material(
+        "red",
+        MaterialDesc::Lambertian {
+            albedo: vec3(0.65, 0.05, 0.05),
+        },
+    );
+    let white = scene.add_material(
+        "white",
+        MaterialDesc::Lambertian {
+            albedo: vec3(0.73, 0.73, 0.73),
+        },
+    );
+    let green = scene.add_material(
+        "green",
+        MaterialDesc::Lambertian {
+            albedo: vec3(0.12, 0.45, 0.15),
+        },
+    );
+    let light = scene.add_material(
+        "light",
+        MaterialDesc::DiffuseLight {
+            emit: vec3(15, 15, 15),
+        },
+    );
+
+    let wall = |plane, k, flip, material: &String| ObjectDesc::Rect {
+        plane,
+        a0: 0.0,
+        a1: 555.0,
+        b0: 0.0,
+        b1: 555.0,
+        k,
+        flip,
+        material: material.clone(),
+    };
+    scene.objects = vec![
+        wall(RectPlane::YZ, 555.0, true, &green),
+        wall(RectPlane::YZ, 0.0, false, &red),
+        ObjectDesc::Rect {
+            plane: RectPlane::XZ,
+            a0: 213.0,
+            a1: 343.0,
+            b0: 227.0,
+            b1: 332.0,
+            k: 554.0,
+            flip: true,
+            material: light,
+        },
+        wall(RectPlane::XZ, 555.0, true, &white),
+        wall(RectPlane::XZ, 0.0, false, &white),
+        wall(RectPlane::XY, 555.0, true, &white),
+        ObjectDesc::Box {
+            min: vec3(130, 0, 65),
+            max: vec3(295, 165, 230),
+            material: white.clone(),
+        },
+        ObjectDesc::Box {
+            min: vec3(265, 0, 295),
+            max: vec3(430, 330, 460),
+            material: white,
+        },
+    ];
+    scene
+}
+
+pub const SCENE_NAMES: &[&str] = &[
+    "random_scene",
+    "simple_spheres",
+    "sphere_tree",
+    "lit_spheres",
+    "cornell_box",
+];
+
+/// Describes one of the scenes above by its function name.
+pub fn named_description(name: &str) -> Option<SceneDescription> {
+    match name {
+        "random_scene" => Some(random_scene_description()),
+        "simple_spheres" => Some(simple_spheres_description()),
+        "sphere_tree" => Some(sphere_tree_description()),
+        "lit_spheres" => Some(lit_spheres_description()),
+        "cornell_box" => Some(cornell_box_description()),
+        _ => None,
+    }
 }
@@ -0,0 +1,1113 @@
+//!
+//! A line based text format for describing scenes.
+//!
+//! Every non-empty line is a statement. `#` starts a comment that runs to the
+//! end of the line. Materials are declared once by name and can be shared by
+//! any number of objects declared after them.
+//!
+//! ```text
+//! camera look_from=13,4,5 look_at=0,0,0 vfov=20 aperture=0.1 focus_dist=10
+//! bvh sah bins=12 max_leaf_size=4
+//! background gradient bottom=1,1,1 top=0.5,0.7,1
+//! material ground lambertian albedo=0.5,0.5,0.5
+//! material glass dielectric ref_idx=1.5
+//! material lamp diffuse_light emit=4,4,4
+//! sphere center=0,-1000,0 radius=1000 material=ground
+//! hollow_sphere center=0,1,0 radius=1 thickness=0.05 material=glass
+//! moving_sphere center0=2,0.2,1 center1=2,0.4,1 time0=0 time1=1 radius=0.2 material=glass
+//! xz_rect x0=-1 x1=1 z0=-1 z1=1 k=5 flip=on material=lamp
+//! box min=-3,0,-3 max=-2,1,-2 material=ground
+//! ```
+//!
+//! `bvh` is `off`, `median`, `sah` or `on`, which is `sah` with its default
+//! settings.
+//!
+//! Rectangles (`xy_rect`, `xz_rect`, `yz_rect`) face the positive axis unless
+//! `flip=on` is given.
+//!
+//! The background is one of `constant color=r,g,b`, `gradient bottom=r,g,b
+//! top=r,g,b` or `environment file=studio.hdr`; relative environment paths are
+//! resolved against the directory of the scene file.
+use background::{Background, Constant, EnvironmentMap, Gradient};
+use bvh::LinearBvh;
+use camera::Camera;
+use csg::Csg;
+use geo::{
+    BoxShape, BvhBuilder, FlipNormals, Hittable, HittableList, MovingSphere, Sphere, XYRect,
+    XZRect, YZRect, SAH_BINS, SAH_MAX_LEAF_SIZE,
+};
+use hdr::HdrError;
+use material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use scene::Scene;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use vec3::{vec3, Vec3};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraDesc {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub v_up: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    pub time0: f64,
+    pub time1: f64,
+}
+
+impl Default for CameraDesc {
+    fn default() -> CameraDesc {
+        CameraDesc {
+            look_from: vec3(13, 4, 5),
+            look_at: vec3(0, 0, 0),
+            v_up: vec3(0, 1, 0),
+            vfov: 20.0,
+            aperture: 0.1,
+            focus_dist: 10.0,
+            time0: 0.0,
+            time1: 1.0,
+        }
+    }
+}
+
+impl CameraDesc {
+    pub fn build(&self, aspect: f64) -> Camera {
+        Camera::new(
+            self.look_from,
+            self.look_at,
+            self.v_up,
+            self.vfov,
+            aspect,
+            self.aperture,
+            self.focus_dist,
+            self.time0,
+            self.time1,
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MaterialDesc {
+    Lambertian { albedo: Vec3 },
+    Metal { albedo: Vec3, fuzz: f64 },
+    Dielectric { ref_idx: f64 },
+    DiffuseLight { emit: Vec3 },
+}
+
+impl MaterialDesc {
+    pub fn build(&self) -> Arc<dyn Material> {
+        match *self {
+            MaterialDesc::Lambertian { albedo } => Arc::new(Lambertian { albedo }),
+            MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal { albedo, fuzz }),
+            MaterialDesc::Dielectric { ref_idx } => Arc::new(Dielectric { ref_idx }),
+            MaterialDesc::DiffuseLight { emit } => Arc::new(DiffuseLight { emit }),
+        }
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        matches!(*self, MaterialDesc::DiffuseLight { .. })
+    }
+}
+
+/// The plane an axis-aligned rectangle lies in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RectPlane {
+    XY,
+    XZ,
+    YZ,
+}
+
+impl RectPlane {
+    pub fn keyword(self) -> &'static str {
+        match self {
+            RectPlane::XY => "xy_rect",
+            RectPlane::XZ => "xz_rect",
+            RectPlane::YZ => "yz_rect",
+        }
+    }
+
+    /// The attribute names of the two spanned axes.
+    fn keys(self) -> [&'static str; 4] {
+        match self {
+            RectPlane::XY => ["x0", "x1", "y0", "y1"],
+            RectPlane::XZ => ["x0", "x1", "z0", "z1"],
+            RectPlane::YZ => ["y0", "y1", "z0", "z1"],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ObjectDesc {
+    Sphere {
+        center: Vec3,
+        radius: f64,
+        material: String,
+    },
+    /// A ball with a ball of `radius - thickness` carved out of its middle.
+    HollowSphere {
+        center: Vec3,
+        radius: f64,
+        thickness: f64,
+        material: String,
+    },
+    MovingSphere {
+        center0: Vec3,
+        center1: Vec3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: String,
+    },
+    /// `[a0, a1] x [b0, b1]` on the two axes of `plane`, at `k` on the third.
+    Rect {
+        plane: RectPlane,
+        a0: f64,
+        a1: f64,
+        b0: f64,
+        b1: f64,
+        k: f64,
+        flip: bool,
+        material: String,
+    },
+    Box {
+        min: Vec3,
+        max: Vec3,
+        material: String,
+    },
+}
+
+impl ObjectDesc {
+    pub fn material(&self) -> &str {
+        match *self {
+            ObjectDesc::Sphere { ref material, .. } => material,
+            ObjectDesc::HollowSphere { ref material, .. } => material,
+            ObjectDesc::MovingSphere { ref material, .. } => material,
+            ObjectDesc::Rect { ref material, .. } => material,
+            ObjectDesc::Box { ref material, .. } => material,
+        }
+    }
+
+    fn build(&self, material: Arc<dyn Material>) -> Box<dyn Hittable> {
+        match *self {
+            ObjectDesc::Sphere { center, radius, .. } => {
+                Box::new(Sphere::new(center, radius, material))
+            }
+            ObjectDesc::HollowSphere {
+                center,
+                radius,
+                thickness,
+                ..
+            } => Box::new(Csg::difference(
+                Box::new(Sphere::new(center, radius, Arc::clone(&material))),
+                Box::new(Sphere::new(center, radius - thickness, material)),
+            )),
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                ..
+            } => Box::new(MovingSphere::new(
+                center0, center1, time0, time1, radius, material,
+            )),
+            ObjectDesc::Rect {
+                plane,
+                a0,
+                a1,
+                b0,
+                b1,
+                k,
+                flip,
+                ..
+            } => {
+                let rect: Box<dyn Hittable> = match plane {
+                    RectPlane::XY => Box::new(XYRect {
+                        x0: a0,
+                        x1: a1,
+                        y0: b0,
+                        y1: b1,
+                        k,
+                        material,
+                    }),
+                    RectPlane::XZ => Box::new(XZRect {
+                        x0: a0,
+                        x1: a1,
+                        z0: b0,
+                        z1: b1,
+                        k,
+                        material,
+                    }),
+                    RectPlane::YZ => Box::new(YZRect {
+                        y0: a0,
+                        y1: a1,
+                        z0: b0,
+                        z1: b1,
+                        k,
+                        material,
+                    }),
+                };
+                if flip {
+                    Box::new(FlipNormals { inner: rect })
+                } else {
+                    rect
+                }
+            }
+            ObjectDesc::Box { min, max, .. } => Box::new(BoxShape::new(min, max, material)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BackgroundDesc {
+    Constant { color: Vec3 },
+    Gradient { bottom: Vec3, top: Vec3 },
+    Environment { file: String },
+}
+
+impl Default for BackgroundDesc {
+    fn default() -> BackgroundDesc {
+        let g = Gradient::default();
+        BackgroundDesc::Gradient {
+            bottom: g.bottom,
+            top: g.top,
+        }
+    }
+}
+
+impl BackgroundDesc {
+    pub fn build(&self) -> Result<Box<dyn Background>, HdrError> {
+        Ok(match *self {
+            BackgroundDesc::Constant { color } => Box::new(Constant { color }),
+            BackgroundDesc::Gradient { bottom, top } => Box::new(Gradient { bottom, top }),
+            BackgroundDesc::Environment { ref file } => Box::new(EnvironmentMap::load(file)?),
+        })
+    }
+}
+
+/// Everything needed to build a world and a camera, in a form that can be
+/// parsed from and written back to text.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SceneDescription {
+    pub camera: CameraDesc,
+    /// How to build a BVH over the objects, or `None` for a flat list.
+    pub bvh: Option<BvhBuilder>,
+    pub background: BackgroundDesc,
+    pub materials: Vec<(String, MaterialDesc)>,
+    pub objects: Vec<ObjectDesc>,
+}
+
+impl SceneDescription {
+    /// Adds a material and returns its name, for use by objects.
+    pub fn add_material<S: Into<String>>(&mut self, name: S, material: MaterialDesc) -> String {
+        let name = name.into();
+        self.materials.push((name.clone(), material));
+        name
+    }
+
+    /// Builds every object, sharing one material instance per name.
+    ///
+    /// Panics if an object refers to a material that was never added; parsed
+    /// descriptions are checked for this up front.
+    pub fn build_objects(&self) -> Vec<Box<dyn Hittable>> {
+        self.build_where(|_| true)
+    }
+
+    /// Builds the emissive objects that light sampling can aim at. Other
+    /// emitters are left out and are only found by following the BSDF.
+    pub fn build_lights(&self) -> HittableList {
+        let mut list = self.build_where(MaterialDesc::is_emissive);
+        list.retain(|light| light.is_sampleable());
+        HittableList { list }
+    }
+
+    fn build_where<F: Fn(&MaterialDesc) -> bool>(&self, filter: F) -> Vec<Box<dyn Hittable>> {
+        let materials: HashMap<&str, (&MaterialDesc, Arc<dyn Material>)> = self
+            .materials
+            .iter()
+            .map(|(name, m)| (name.as_str(), (m, m.build())))
+            .collect();
+
+        self.objects
+            .iter()
+            .filter_map(|o| match materials.get(o.material()) {
+                Some((desc, m)) if filter(desc) => Some(o.build(Arc::clone(m))),
+                Some(_) => None,
+                None => panic!("undefined material '{}'", o.material()),
+            })
+            .collect()
+    }
+
+    pub fn build_world(&self) -> Box<dyn Hittable> {
+        let objects = self.build_objects();
+        match self.bvh {
+            Some(builder) => Box::new(LinearBvh::new(
+                objects,
+                self.camera.time0,
+                self.camera.time1,
+                builder,
+            )),
+            None => Box::new(HittableList { list: objects }),
+        }
+    }
+
+    pub fn build(&self, aspect: f64) -> Result<Scene, SceneFileError> {
+        Ok(Scene {
+            world: self.build_world(),
+            lights: self.build_lights(),
+            camera: self.camera.build(aspect),
+            background: self.background.build()?,
+        })
+    }
+
+    pub fn parse(src: &str) -> Result<SceneDescription, ParseError> {
+        Parser::default().parse(src)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SceneDescription, SceneFileError> {
+        let path = path.as_ref();
+        let src = fs::read_to_string(path)?;
+        let mut scene = SceneDescription::parse(&src)?;
+
+        if let BackgroundDesc::Environment { ref mut file } = scene.background {
+            if let Some(dir) = path.parent() {
+                *file = dir.join(&file).to_string_lossy().into_owned();
+            }
+        }
+
+        Ok(scene)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+fn fmt_vec3(v: Vec3) -> String {
+    format!("{},{},{}", v.x(), v.y(), v.z())
+}
+
+impl fmt::Display for SceneDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = &self.camera;
+        writeln!(
+            f,
+            "camera look_from={} look_at={} v_up={} vfov={} aperture={} focus_dist={} time0={} time1={}",
+            fmt_vec3(c.look_from),
+            fmt_vec3(c.look_at),
+            fmt_vec3(c.v_up),
+            c.vfov,
+            c.aperture,
+            c.focus_dist,
+            c.time0,
+            c.time1
+        )?;
+        match self.bvh {
+            None => writeln!(f, "bvh off")?,
+            Some(BvhBuilder::Median) => writeln!(f, "bvh median")?,
+            Some(BvhBuilder::Sah {
+                bins,
+                max_leaf_size,
+            }) => writeln!(f, "bvh sah bins={} max_leaf_size={}", bins, max_leaf_size)?,
+        }
+        match self.background {
+            BackgroundDesc::Constant { color } => {
+                writeln!(f, "background constant color={}", fmt_vec3(color))?
+            }
+            BackgroundDesc::Gradient { bottom, top } => writeln!(
+                f,
+                "background gradient bottom={} top={}",
+                fmt_vec3(bottom),
+                fmt_vec3(top)
+            )?,
+            BackgroundDesc::Environment { ref file } => {
+                writeln!(f, "background environment file={}", file)?
+            }
+        }
+
+        for (name, m) in &self.materials {
+            match *m {
+                MaterialDesc::Lambertian { albedo } => writeln!(
+                    f,
+                    "material {} lambertian albedo={}",
+                    name,
+                    fmt_vec3(albedo)
+                )?,
+                MaterialDesc::Metal { albedo, fuzz } => writeln!(
+                    f,
+                    "material {} metal albedo={} fuzz={}",
+                    name,
+                    fmt_vec3(albedo),
+                    fuzz
+                )?,
+                MaterialDesc::Dielectric { ref_idx } => {
+                    writeln!(f, "material {} dielectric ref_idx={}", name, ref_idx)?
+                }
+                MaterialDesc::DiffuseLight { emit } => {
+                    writeln!(f, "material {} diffuse_light emit={}", name, fmt_vec3(emit))?
+                }
+            }
+        }
+
+        for o in &self.objects {
+            match *o {
+                ObjectDesc::Sphere {
+                    center,
+                    radius,
+                    ref material,
+                } => writeln!(
+                    f,
+                    "sphere center={} radius={} material={}",
+                    fmt_vec3(center),
+                    radius,
+                    material
+                )?,
+                ObjectDesc::HollowSphere {
+                    center,
+                    radius,
+                    thickness,
+                    ref material,
+                } => writeln!(
+                    f,
+                    "hollow_sphere center={} radius={} thickness={} material={}",
+                    fmt_vec3(center),
+                    radius,
+                    thickness,
+                    material
+                )?,
+                ObjectDesc::MovingSphere {
+                    center0,
+                    center1,
+                    time0,
+                    time1,
+                    radius,
+                    ref material,
+                } => writeln!(
+                    f,
+                    "moving_sphere center0={} center1={} time0={} time1={} radius={} material={}",
+                    fmt_vec3(center0),
+                    fmt_vec3(center1),
+                    time0,
+                    time1,
+                    radius,
+                    material
+                )?,
+                ObjectDesc::Rect {
+                    plane,
+                    a0,
+                    a1,
+                    b0,
+                    b1,
+                    k,
+                    flip,
+                    ref material,
+                } => {
+                    let [ka0, ka1, kb0, kb1] = plane.keys();
+                    write!(
+                        f,
+                        "{} {}={} {}={} {}={} {}={} k={}",
+                        plane.keyword(),
+                        ka0,
+                        a0,
+                        ka1,
+                        a1,
+                        kb0,
+                        b0,
+                        kb1,
+                        b1,
+                        k
+                    )?;
+                    if flip {
+                        write!(f, " flip=on")?;
+                    }
+                    writeln!(f, " material={}", material)?
+                }
+                ObjectDesc::Box {
+                    min,
+                    max,
+                    ref material,
+                } => writeln!(
+                    f,
+                    "box min={} max={} material={}",
+                    fmt_vec3(min),
+                    fmt_vec3(max),
+                    material
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A problem found while parsing, with a 1-based line and column.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl error::Error for ParseError {}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(io::Error),
+    Parse(ParseError),
+    Image(HdrError),
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SceneFileError::Io(ref e) => write!(f, "{}", e),
+            SceneFileError::Parse(ref e) => write!(f, "{}", e),
+            SceneFileError::Image(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for SceneFileError {}
+
+impl From<io::Error> for SceneFileError {
+    fn from(e: io::Error) -> SceneFileError {
+        SceneFileError::Io(e)
+    }
+}
+
+impl From<HdrError> for SceneFileError {
+    fn from(e: HdrError) -> SceneFileError {
+        SceneFileError::Image(e)
+    }
+}
+
+impl From<ParseError> for SceneFileError {
+    fn from(e: ParseError) -> SceneFileError {
+        SceneFileError::Parse(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Token<'a> {
+    fn error<S: Into<String>>(&self, message: S) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+fn tokenize(line: &str, line_no: usize) -> Vec<Token<'_>> {
+    let line = match line.find('#') {
+        Some(idx) => &line[..idx],
+        None => line,
+    };
+
+    let mut tokens = vec![];
+    let mut start = None;
+    for (idx, ch) in line.char_indices().chain(Some((line.len(), ' '))) {
+        match (start, ch.is_whitespace()) {
+            (None, false) => start = Some(idx),
+            (Some(s), true) => {
+                tokens.push(Token {
+                    text: &line[s..idx],
+                    line: line_no,
+                    column: line[..s].chars().count() + 1,
+                });
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    tokens
+}
+
+/// The `key=value` pairs that follow a statement keyword.
+struct Attrs<'a> {
+    keyword: Token<'a>,
+    values: Vec<(&'a str, Token<'a>)>,
+}
+
+impl<'a> Attrs<'a> {
+    fn new(
+        keyword: Token<'a>,
+        tokens: &[Token<'a>],
+        allowed: &[&str],
+    ) -> Result<Attrs<'a>, ParseError> {
+        let mut values: Vec<(&'a str, Token<'a>)> = vec![];
+        for tok in tokens {
+            let idx = tok
+                .text
+                .find('=')
+                .ok_or_else(|| tok.error(format!("expected key=value, found '{}'", tok.text)))?;
+            let key = &tok.text[..idx];
+            if !allowed.contains(&key) {
+                return Err(tok.error(format!("unknown attribute '{}' for {}", key, keyword.text)));
+            }
+            if values.iter().any(|(k, _)| *k == key) {
+                return Err(tok.error(format!("duplicate attribute '{}'", key)));
+            }
+            let value = Token {
+                text: &tok.text[idx + 1..],
+                line: tok.line,
+                column: tok.column + idx + 1,
+            };
+            values.push((key, value));
+        }
+        Ok(Attrs { keyword, values })
+    }
+
+    fn get(&self, key: &str) -> Option<Token<'a>> {
+        self.values.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+    }
+
+    fn required(&self, key: &str) -> Result<Token<'a>, ParseError> {
+        self.get(key).ok_or_else(|| {
+            self.keyword
+                .error(format!("{} is missing '{}'", self.keyword.text, key))
+        })
+    }
+
+    fn f64(&self, key: &str, default: f64) -> Result<f64, ParseError> {
+        match self.get(key) {
+            Some(tok) => parse_f64(tok),
+            None => Ok(default),
+        }
+    }
+
+    fn vec3(&self, key: &str, default: Vec3) -> Result<Vec3, ParseError> {
+        match self.get(key) {
+            Some(tok) => parse_vec3(tok),
+            None => Ok(default),
+        }
+    }
+
+    fn flag(&self, key: &str) -> Result<bool, ParseError> {
+        match self.get(key) {
+            Some(tok) if tok.text == "on" => Ok(true),
+            Some(tok) if tok.text == "off" => Ok(false),
+            Some(tok) => Err(tok.error(format!("expected on or off, found '{}'", tok.text))),
+            None => Ok(false),
+        }
+    }
+
+    fn count(&self, key: &str, default: usize) -> Result<usize, ParseError> {
+        match self.get(key) {
+            Some(tok) => match tok.text.parse::<usize>() {
+                Ok(v) if v > 0 => Ok(v),
+                _ => Err(tok.error(format!(
+                    "expected a positive whole number, found '{}'",
+                    tok.text
+                ))),
+            },
+            None => Ok(default),
+        }
+    }
+
+    fn required_f64(&self, key: &str) -> Result<f64, ParseError> {
+        parse_f64(self.required(key)?)
+    }
+
+    fn required_vec3(&self, key: &str) -> Result<Vec3, ParseError> {
+        parse_vec3(self.required(key)?)
+    }
+}
+
+fn parse_f64(tok: Token) -> Result<f64, ParseError> {
+    match tok.text.parse::<f64>() {
+        Ok(v) if v.is_finite() => Ok(v),
+        _ => Err(tok.error(format!("expected a number, found '{}'", tok.text))),
+    }
+}
+
+fn parse_vec3(tok: Token) -> Result<Vec3, ParseError> {
+    let mut parts = [0.0; 3];
+    let mut count = 0;
+    let mut offset = 0;
+    for part in tok.text.split(',') {
+        if count == 3 {
+            count += 1;
+            break;
+        }
+        parts[count] = parse_f64(Token {
+            text: part,
+            line: tok.line,
+            column: tok.column + offset,
+        })?;
+        offset += part.len() + 1;
+        count += 1;
+    }
+    if count != 3 {
+        return Err(tok.error(format!("expected x,y,z, found '{}'", tok.text)));
+    }
+    Ok(Vec3::new(parts))
+}
+
+#[derive(Default)]
+struct Parser {
+    scene: SceneDescription,
+    seen_camera: bool,
+    seen_bvh: bool,
+    seen_background: bool,
+}
+
+impl Parser {
+    fn parse(mut self, src: &str) -> Result<SceneDescription, ParseError> {
+        for (idx, line) in src.lines().enumerate() {
+            let tokens = tokenize(line, idx + 1);
+            if let Some((keyword, rest)) = tokens.split_first() {
+                self.statement(*keyword, rest)?;
+            }
+        }
+        Ok(self.scene)
+    }
+
+    fn material_ref(&self, attrs: &Attrs) -> Result<String, ParseError> {
+        let tok = attrs.required("material")?;
+        if self
+            .scene
+            .materials
+            .iter()
+            .any(|(name, _)| name == tok.text)
+        {
+            Ok(tok.text.to_string())
+        } else {
+            Err(tok.error(format!("undefined material '{}'", tok.text)))
+        }
+    }
+
+    fn statement<'a>(&mut self, keyword: Token<'a>, rest: &[Token<'a>]) -> Result<(), ParseError> {
+        match keyword.text {
+            "camera" => {
+                if self.seen_camera {
+                    return Err(keyword.error("camera is already defined"));
+                }
+                self.seen_camera = true;
+                let attrs = Attrs::new(
+                    keyword,
+                    rest,
+                    &[
+                        "look_from",
+                        "look_at",
+                        "v_up",
+                        "vfov",
+                        "aperture",
+                        "focus_dist",
+                        "time0",
+                        "time1",
+                    ],
+                )?;
+                let d = CameraDesc::default();
+                self.scene.camera = CameraDesc {
+                    look_from: attrs.vec3("look_from", d.look_from)?,
+                    look_at: attrs.vec3("look_at", d.look_at)?,
+                    v_up: attrs.vec3("v_up", d.v_up)?,
+                    vfov: attrs.f64("vfov", d.vfov)?,
+                    aperture: attrs.f64("aperture", d.aperture)?,
+                    focus_dist: attrs.f64("focus_dist", d.focus_dist)?,
+                    time0: attrs.f64("time0", d.time0)?,
+                    time1: attrs.f64("time1", d.time1)?,
+                };
+            }
+
+            "bvh" => {
+                if self.seen_bvh {
+                    return Err(keyword.error("bvh is already set"));
+                }
+                self.seen_bvh = true;
+                self.scene.bvh = match rest {
+                    [v] if v.text == "on" => Some(BvhBuilder::default()),
+                    [v] if v.text == "off" => None,
+                    [v] if v.text == "median" => Some(BvhBuilder::Median),
+                    [v, attrs @ ..] if v.text == "sah" => {
+                        let attrs = Attrs::new(*v, attrs, &["bins", "max_leaf_size"])?;
+                        let bins = attrs.count("bins", SAH_BINS)?;
+                        if bins < 2 {
+                            return Err(attrs.required("bins")?.error("sah needs at least 2 bins"));
+                        }
+                        Some(BvhBuilder::Sah {
+                            bins,
+                            max_leaf_size: attrs.count("max_leaf_size", SAH_MAX_LEAF_SIZE)?,
+                        })
+                    }
+                    [v] => {
+                        return Err(v.error(format!(
+                            "expected on, off, sah or median, found '{}'",
+                            v.text
+                        )))
+                    }
+                    [] => return Err(keyword.error("bvh expects on, off, sah or median")),
+                    [_, extra, ..] => return Err(extra.error("unexpected token")),
+                };
+            }
+
+            "background" => {
+                if self.seen_background {
+                    return Err(keyword.error("background is already defined"));
+                }
+                self.seen_background = true;
+                let (kind, rest) = match rest.split_first() {
+                    Some((kind, rest)) => (kind, rest),
+                    None => return Err(keyword.error("background expects a type")),
+                };
+                self.scene.background = match kind.text {
+                    "constant" => {
+                        let attrs = Attrs::new(*kind, rest, &["color"])?;
+                        BackgroundDesc::Constant {
+                            color: attrs.required_vec3("color")?,
+                        }
+                    }
+                    "gradient" => {
+                        let attrs = Attrs::new(*kind, rest, &["bottom", "top"])?;
+                        let d = Gradient::default();
+                        BackgroundDesc::Gradient {
+                            bottom: attrs.vec3("bottom", d.bottom)?,
+                            top: attrs.vec3("top", d.top)?,
+                        }
+                    }
+                    "environment" => {
+                        let attrs = Attrs::new(*kind, rest, &["file"])?;
+                        BackgroundDesc::Environment {
+                            file: attrs.required("file")?.text.to_string(),
+                        }
+                    }
+                    other => return Err(kind.error(format!("unknown background type '{}'", other))),
+                };
+            }
+
+            "material" => {
+                let (name, kind, rest) = match rest {
+                    [name, kind, rest @ ..] => (name, kind, rest),
+                    _ => return Err(keyword.error("material expects a name and a type")),
+                };
+                if self.scene.materials.iter().any(|(n, _)| n == name.text) {
+                    return Err(name.error(format!("material '{}' is already defined", name.text)));
+                }
+                let material = match kind.text {
+                    "lambertian" => {
+                        let attrs = Attrs::new(*kind, rest, &["albedo"])?;
+                        MaterialDesc::Lambertian {
+                            albedo: attrs.required_vec3("albedo")?,
+                        }
+                    }
+                    "metal" => {
+                        let attrs = Attrs::new(*kind, rest, &["albedo", "fuzz"])?;
+                        MaterialDesc::Metal {
+                            albedo: attrs.required_vec3("albedo")?,
+                            fuzz: attrs.f64("fuzz", 0.0)?,
+                        }
+                    }
+                    "dielectric" => {
+                        let attrs = Attrs::new(*kind, rest, &["ref_idx"])?;
+                        MaterialDesc::Dielectric {
+                            ref_idx: attrs.required_f64("ref_idx")?,
+                        }
+                    }
+                    "diffuse_light" => {
+                        let attrs = Attrs::new(*kind, rest, &["emit"])?;
+                        MaterialDesc::DiffuseLight {
+                            emit: attrs.required_vec3("emit")?,
+                        }
+                    }
+                    other => return Err(kind.error(format!("unknown material type '{}'", other))),
+                };
+                self.scene.add_material(name.text, material);
+            }
+
+            "sphere" => {
+                let attrs = Attrs::new(keyword, rest, &["center", "radius", "material"])?;
+                let object = ObjectDesc::Sphere {
+                    center: attrs.required_vec3("center")?,
+                    radius: attrs.required_f64("radius")?,
+                    material: self.material_ref(&attrs)?,
+                };
+                self.scene.objects.push(object);
+            }
+
+            "hollow_sphere" => {
+                let attrs = Attrs::new(
+                    keyword,
+                    rest,
+                    &["center", "radius", "thickness", "material"],
+                )?;
+                let radius = attrs.required_f64("radius")?;
+                let thickness = attrs.required_f64("thickness")?;
+                if thickness <= 0.0 || thickness >= radius {
+                    return Err(attrs
+                        .required("thickness")?
+                        .error("thickness must be between 0 and the radius"));
+                }
+                let object = ObjectDesc::HollowSphere {
+                    center: attrs.required_vec3("center")?,
+                    radius,
+                    thickness,
+                    material: self.material_ref(&attrs)?,
+                };
+                self.scene.objects.push(object);
+            }
+
+            "moving_sphere" => {
+                let attrs = Attrs::new(
+                    keyword,
+                    rest,
+                    &["center0", "center1", "time0", "time1", "radius", "material"],
+                )?;
+                let object = ObjectDesc::MovingSphere {
+                    center0: attrs.required_vec3("center0")?,
+                    center1: attrs.required_vec3("center1")?,
+                    time0: attrs.f64("time0", 0.0)?,
+                    time1: attrs.f64("time1", 1.0)?,
+                    radius: attrs.required_f64("radius")?,
+                    material: self.material_ref(&attrs)?,
+                };
+                self.scene.objects.push(object);
+            }
+
+            "xy_rect" | "xz_rect" | "yz_rect" => {
+                let plane = match keyword.text {
+                    "xy_rect" => RectPlane::XY,
+                    "xz_rect" => RectPlane::XZ,
+                    _ => RectPlane::YZ,
+                };
+                let [a0, a1, b0, b1] = plane.keys();
+                let attrs = Attrs::new(keyword, rest, &[a0, a1, b0, b1, "k", "flip", "material"])?;
+                let object = ObjectDesc::Rect {
+                    plane,
+                    a0: attrs.required_f64(a0)?,
+                    a1: attrs.required_f64(a1)?,
+                    b0: attrs.required_f64(b0)?,
+                    b1: attrs.required_f64(b1)?,
+                    k: attrs.required_f64("k")?,
+                    flip: attrs.flag("flip")?,
+                    material: self.material_ref(&attrs)?,
+                };
+                self.scene.objects.push(object);
+            }
+
+            "box" => {
+                let attrs = Attrs::new(keyword, rest, &["min", "max", "material"])?;
+                let object = ObjectDesc::Box {
+                    min: attrs.required_vec3("min")?,
+                    max: attrs.required_vec3("max")?,
+                    material: self.material_ref(&attrs)?,
+                };
+                self.scene.objects.push(object);
+            }
+
+            other => return Err(keyword.error(format!("unknown statement '{}'", other))),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: &str = "
+# a small test scene
+camera look_from=0,1,5 vfov=45
+bvh on
+background constant color=0,0,0
+material ground lambertian albedo=0.5,0.5,0.5
+material gold metal albedo=0.8,0.6,0.2 fuzz=0.1
+material lamp diffuse_light emit=4,4,4
+sphere center=0,-1000,0 radius=1000 material=ground
+sphere center=0,1,0 radius=1 material=gold  # trailing comment
+hollow_sphere center=0,3,0 radius=1 thickness=0.1 material=gold
+moving_sphere center0=2,0.2,1 center1=2,0.4,1 radius=0.2 material=gold
+sphere center=0,5,0 radius=0.5 material=lamp
+xz_rect x0=-1 x1=1 z0=-1 z1=1 k=6 flip=on material=lamp
+box min=-3,0,-3 max=-2,1,-2 material=ground
+";
+
+    #[test]
+    fn parses_statements() {
+        let scene = SceneDescription::parse(SRC).unwrap();
+        assert_eq!(scene.camera.look_from, vec3(0, 1, 5));
+        assert_eq!(scene.camera.vfov, 45.0);
+        assert_eq!(scene.camera.aperture, CameraDesc::default().aperture);
+        assert_eq!(scene.bvh, Some(BvhBuilder::default()));
+        assert_eq!(
+            scene.background,
+            BackgroundDesc::Constant {
+                color: vec3(0, 0, 0)
+            }
+        );
+        assert_eq!(scene.materials.len(), 3);
+        assert_eq!(scene.objects.len(), 7);
+        assert_eq!(
+            scene.objects[5],
+            ObjectDesc::Rect {
+                plane: RectPlane::XZ,
+                a0: -1.0,
+                a1: 1.0,
+                b0: -1.0,
+                b1: 1.0,
+                k: 6.0,
+                flip: true,
+                material: "lamp".to_string(),
+            }
+        );
+        assert_eq!(scene.objects[2].material(), "gold");
+        assert!(scene
+            .build(2.0)
+            .unwrap()
+            .world
+            .bounding_box(0.0, 1.0)
+            .is_some());
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let scene = SceneDescription::parse(SRC).unwrap();
+        let reparsed = SceneDescription::parse(&scene.to_string()).unwrap();
+        assert_eq!(scene, reparsed);
+    }
+
+    fn error_at(src: &str) -> (usize, usize) {
+        let e = SceneDescription::parse(src).unwrap_err();
+        (e.line, e.column)
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        assert_eq!(error_at("\n  teapot size=1"), (2, 3));
+        assert_eq!(
+            error_at("sphere center=0,0,0 radius=1 material=nope"),
+            (1, 39)
+        );
+        assert_eq!(error_at("sphere center=0,x,0 radius=1"), (1, 17));
+        assert_eq!(error_at("sphere center=0,0,0 material=m"), (1, 1));
+        assert_eq!(
+            error_at("material a lambertian albedo=1,1,1\nmaterial a dielectric ref_idx=1"),
+            (2, 10)
+        );
+        assert_eq!(
+            error_at("material m lambertian albedo=1,1,1\nhollow_sphere center=0,0,0 radius=1 thickness=2 material=m"),
+            (2, 47)
+        );
+        assert_eq!(error_at("bvh maybe"), (1, 5));
+        assert_eq!(error_at("bvh sah bins=1"), (1, 14));
+        assert_eq!(error_at("bvh sah leaves=2"), (1, 9));
+    }
+}
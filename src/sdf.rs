@@ -0,0 +1,349 @@
+//!
+//! Shapes given by signed distance functions, rendered by sphere tracing.
+//!
+//! Distances must never overestimate the true distance to the surface, or the
+//! marcher can step through it. The smooth combinators keep that property.
+use bvh::AABB;
+use geo::{sphere_uv, HitRecord, Hittable};
+use material::Material;
+use std::fmt;
+use std::sync::Arc;
+use vec3::{vec3, Ray, Vec3};
+
+/// A signed distance function: negative inside, positive outside.
+pub trait Sdf: Sync + Send + fmt::Debug {
+    fn distance(&self, p: Vec3) -> f64;
+}
+
+/// Wraps a closure as an `Sdf`.
+#[derive(Clone)]
+pub struct DistanceFn<F>(pub F);
+
+impl<F> fmt::Debug for DistanceFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DistanceFn")
+    }
+}
+
+impl<F: Fn(Vec3) -> f64 + Sync + Send> Sdf for DistanceFn<F> {
+    fn distance(&self, p: Vec3) -> f64 {
+        (self.0)(p)
+    }
+}
+
+/// A sphere around the origin.
+#[derive(Clone, Debug)]
+pub struct SdfSphere {
+    pub radius: f64,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Vec3) -> f64 {
+        p.length() - self.radius
+    }
+}
+
+/// A box around the origin with the given half extents.
+#[derive(Clone, Debug)]
+pub struct SdfBox {
+    pub half_extents: Vec3,
+}
+
+fn box_distance(p: Vec3, half_extents: Vec3) -> f64 {
+    let q = p.map(f64::abs) - half_extents;
+    let outside = q.map(|c| c.max(0.0)).length();
+    let inside = q.max_component().min(0.0);
+    outside + inside
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: Vec3) -> f64 {
+        box_distance(p, self.half_extents)
+    }
+}
+
+/// A box whose edges are rounded off with `radius`, keeping its overall
+/// half extents.
+#[derive(Clone, Debug)]
+pub struct RoundedBox {
+    pub half_extents: Vec3,
+    pub radius: f64,
+}
+
+impl Sdf for RoundedBox {
+    fn distance(&self, p: Vec3) -> f64 {
+        let r = vec3(self.radius, self.radius, self.radius);
+        box_distance(p, self.half_extents - r) - self.radius
+    }
+}
+
+/// A torus around the y axis.
+#[derive(Clone, Debug)]
+pub struct Torus {
+    /// Distance from the center to the middle of the tube.
+    pub major_radius: f64,
+    /// Radius of the tube.
+    pub minor_radius: f64,
+}
+
+impl Sdf for Torus {
+    fn distance(&self, p: Vec3) -> f64 {
+        let ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - self.major_radius;
+        (ring * ring + p.y() * p.y()).sqrt() - self.minor_radius
+    }
+}
+
+/// Moves another distance function by `offset`.
+#[derive(Clone, Debug)]
+pub struct Translated {
+    pub sdf: Arc<dyn Sdf>,
+    pub offset: Vec3,
+}
+
+impl Sdf for Translated {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.sdf.distance(p - self.offset)
+    }
+}
+
+/// Union of two shapes, blended over a distance of about `k`.
+#[derive(Clone, Debug)]
+pub struct SmoothUnion {
+    pub a: Arc<dyn Sdf>,
+    pub b: Arc<dyn Sdf>,
+    pub k: f64,
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: Vec3) -> f64 {
+        let (d1, d2) = (self.a.distance(p), self.b.distance(p));
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0.0, 1.0);
+        d2 + (d1 - d2) * h - self.k * h * (1.0 - h)
+    }
+}
+
+/// `base` with `cut` carved out of it, blended over a distance of about `k`.
+#[derive(Clone, Debug)]
+pub struct SmoothSubtraction {
+    pub base: Arc<dyn Sdf>,
+    pub cut: Arc<dyn Sdf>,
+    pub k: f64,
+}
+
+impl Sdf for SmoothSubtraction {
+    fn distance(&self, p: Vec3) -> f64 {
+        let (d1, d2) = (self.cut.distance(p), self.base.distance(p));
+        let h = (0.5 - 0.5 * (d2 + d1) / self.k).clamp(0.0, 1.0);
+        d2 + (-d1 - d2) * h + self.k * h * (1.0 - h)
+    }
+}
+
+/// A `Hittable` for any distance function, found by sphere tracing within a
+/// user-supplied bounding box.
+#[derive(Clone, Debug)]
+pub struct SdfShape {
+    pub sdf: Arc<dyn Sdf>,
+    /// Must contain the whole surface; marching only happens inside it.
+    pub bbox: AABB,
+    /// How close to the surface counts as a hit.
+    pub epsilon: f64,
+    /// Steps before the marcher gives up and reports a miss.
+    pub max_steps: u32,
+    pub material: Arc<dyn Material>,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Arc<dyn Sdf>, bbox: AABB, material: Arc<dyn Material>) -> SdfShape {
+        SdfShape {
+            sdf,
+            bbox,
+            epsilon: 1e-4,
+            max_steps: 256,
+            material,
+        }
+    }
+
+    pub fn with_epsilon(self, epsilon: f64) -> SdfShape {
+        SdfShape { epsilon, ..self }
+    }
+
+    pub fn with_max_steps(self, max_steps: u32) -> SdfShape {
+        SdfShape { max_steps, ..self }
+    }
+
+    /// The gradient of the distance by central differences.
+    pub fn normal(&self, p: Vec3) -> Vec3 {
+        let h = self.epsilon;
+        let axis = |offset: Vec3| self.sdf.distance(p + offset) - self.sdf.distance(p - offset);
+        vec3(
+            axis(vec3(h, 0.0, 0.0)),
+            axis(vec3(0.0, h, 0.0)),
+            axis(vec3(0.0, 0.0, h)),
+        )
+        .unit_vector()
+    }
+}
+
+impl Hittable for SdfShape {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (mut t, t_end) = self.bbox.hit_interval(r, t_min, t_max)?;
+        let speed = r.direction().length();
+
+        // rays leaving the surface start within epsilon of it; step clear of
+        // it before looking for the next crossing. A march clipped to the
+        // bounding box may begin on the surface too, and that is a real hit.
+        let leaving = t <= t_min;
+        let mut steps = 0;
+        while leaving && self.sdf.distance(r.point_at_parameter(t)).abs() < self.epsilon {
+            t += 2.0 * self.epsilon / speed;
+            steps += 1;
+            if steps >= self.max_steps || t > t_end {
+                return None;
+            }
+        }
+
+        while steps < self.max_steps && t <= t_end {
+            let p = r.point_at_parameter(t);
+            let distance = self.sdf.distance(p).abs();
+            if distance < self.epsilon {
+                let normal = self.normal(p);
+                let (u, v) = sphere_uv(normal);
+                return Some(HitRecord {
+                    t,
+                    p,
+                    normal,
+                    u,
+                    v,
+                    material: Arc::clone(&self.material),
+                    tangent: None,
+                    color: None,
+                });
+            }
+            t += distance / speed;
+            steps += 1;
+        }
+        None
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(self.bbox)
+    }
+
+    fn box_clone(&self) -> Box<dyn Hittable> {
+        Box::new((*self).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::tests::grey;
+    use std::f64;
+
+    fn shape(sdf: Arc<dyn Sdf>, extent: f64) -> SdfShape {
+        SdfShape::new(
+            sdf,
+            AABB {
+                min: vec3(-extent, -extent, -extent),
+                max: vec3(extent, extent, extent),
+            },
+            grey(),
+        )
+    }
+
+    #[test]
+    fn finds_faces_on_a_tight_bounding_box() {
+        let cube = shape(
+            Arc::new(SdfBox {
+                half_extents: vec3(1, 1, 1),
+            }),
+            1.0,
+        );
+        let r = Ray::new(vec3(0.2, 0.3, 5.0), vec3(0, 0, -1), 0.0);
+        let rec = cube.hit(&r, 0.001, f64::MAX).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-3);
+        assert!((rec.normal - vec3(0, 0, 1)).length() < 1e-3);
+    }
+
+    #[test]
+    fn traces_a_sphere() {
+        let ball = shape(Arc::new(SdfSphere { radius: 1.0 }), 1.0);
+        let r = Ray::new(vec3(0.3, 0.0, 5.0), vec3(0.0, 0.0, -2.0), 0.0);
+        let rec = ball.hit(&r, 0.001, f64::MAX).unwrap();
+        let expected = vec3(0.3, 0.0, (1.0f64 - 0.09).sqrt());
+        assert!((rec.p - expected).length() < 1e-3);
+        assert!((rec.normal - expected).length() < 1e-3);
+
+        // leaving the surface from inside finds the far side
+        let inside = Ray::new(rec.p, vec3(0, 0, -1), 0.0);
+        let exit = ball.hit(&inside, 0.001, f64::MAX).unwrap();
+        assert!((exit.p.z() + expected.z()).abs() < 1e-3);
+        assert!(exit.normal.z() < 0.0);
+    }
+
+    #[test]
+    fn torus_hole_and_steps() {
+        let torus = shape(
+            Arc::new(Torus {
+                major_radius: 1.0,
+                minor_radius: 0.25,
+            }),
+            1.5,
+        );
+        let through_hole = Ray::new(vec3(0, 5, 0), vec3(0, -1, 0), 0.0);
+        assert!(torus.hit(&through_hole, 0.001, f64::MAX).is_none());
+        let onto_ring = Ray::new(vec3(1, 5, 0), vec3(0, -1, 0), 0.0);
+        let rec = torus.hit(&onto_ring, 0.001, f64::MAX).unwrap();
+        assert!((rec.p.y() - 0.25).abs() < 1e-3);
+
+        let starved = torus.clone().with_max_steps(1);
+        let grazing = Ray::new(vec3(-5.0, 0.3, 0.0), vec3(1, 0, 0), 0.0);
+        assert!(starved.hit(&grazing, 0.001, f64::MAX).is_none());
+    }
+
+    #[test]
+    fn combinators() {
+        let rounded = RoundedBox {
+            half_extents: vec3(1, 1, 1),
+            radius: 0.25,
+        };
+        assert!(rounded.distance(vec3(1.0, 0.0, 0.0)).abs() < 1e-12);
+        // the corner is cut off
+        assert!(rounded.distance(vec3(1, 1, 1)) > 0.0);
+        let plain = SdfBox {
+            half_extents: vec3(1, 1, 1),
+        };
+        assert!(plain.distance(vec3(1, 1, 1)).abs() < 1e-12);
+
+        let a: Arc<dyn Sdf> = Arc::new(Translated {
+            sdf: Arc::new(SdfSphere { radius: 1.0 }),
+            offset: vec3(-1, 0, 0),
+        });
+        let b: Arc<dyn Sdf> = Arc::new(Translated {
+            sdf: Arc::new(SdfSphere { radius: 1.0 }),
+            offset: vec3(1, 0, 0),
+        });
+        let blob = SmoothUnion {
+            a: Arc::clone(&a),
+            b: Arc::clone(&b),
+            k: 0.5,
+        };
+        // the blend bulges out where the two spheres meet
+        let neck = vec3(0.0, 0.05, 0.0);
+        assert!(blob.distance(neck) < a.distance(neck).min(b.distance(neck)));
+        let far = vec3(-2.5, 0.0, 0.0);
+        assert!((blob.distance(far) - a.distance(far)).abs() < 1e-12);
+
+        let bitten = SmoothSubtraction {
+            base: a,
+            cut: b,
+            k: 0.1,
+        };
+        assert!(bitten.distance(vec3(0.5, 0.0, 0.0)) > 0.0);
+        assert!(bitten.distance(vec3(-1.5, 0.0, 0.0)) < 0.0);
+
+        let custom = shape(Arc::new(DistanceFn(|p: Vec3| p.y() + 0.5)), 1.0);
+        let down = Ray::new(vec3(0, 5, 0), vec3(0, -1, 0), 0.0);
+        assert!((custom.hit(&down, 0.001, f64::MAX).unwrap().p.y() + 0.5).abs() < 1e-3);
+    }
+}
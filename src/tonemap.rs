@@ -0,0 +1,132 @@
+//!
+//! Turning linear HDR film values into 8 bit sRGB
+use vec3::{vec3, Vec3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    /// Clip everything above 1.
+    Clamp,
+    /// `L / (1 + L)` on luminance, so hues are kept.
+    Reinhard,
+    /// Reinhard with a white point: luminance `white` maps to exactly 1.
+    ExtendedReinhard { white: f64 },
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve, per channel.
+    Aces,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapper {
+    pub operator: ToneMap,
+    /// Exposure adjustment in stops, applied before the operator.
+    pub exposure: f64,
+}
+
+impl Default for ToneMapper {
+    fn default() -> ToneMapper {
+        ToneMapper {
+            operator: ToneMap::Clamp,
+            exposure: 0.0,
+        }
+    }
+}
+
+pub fn luminance(c: Vec3) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+/// The sRGB opto-electronic transfer function for a value in [0, 1].
+pub fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn scale_luminance(c: Vec3, map: impl Fn(f64) -> f64) -> Vec3 {
+    let l = luminance(c);
+    if l <= 0.0 {
+        vec3(0.0, 0.0, 0.0)
+    } else {
+        c * (map(l) / l)
+    }
+}
+
+fn aces(x: f64) -> f64 {
+    let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+    (x * (a * x + b)) / (x * (c * x + d) + e)
+}
+
+impl ToneMapper {
+    /// Maps a linear colour to display referred linear values in [0, 1].
+    pub fn map(&self, c: Vec3) -> Vec3 {
+        let c = c.map(|v| if v.is_finite() { v.max(0.0) } else { 0.0 }) * 2f64.powf(self.exposure);
+        let mapped = match self.operator {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => scale_luminance(c, |l| l / (1.0 + l)),
+            ToneMap::ExtendedReinhard { white } => {
+                scale_luminance(c, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMap::Aces => c.map(aces),
+        };
+        mapped.map(|v| v.clamp(0.0, 1.0))
+    }
+
+    /// Tone maps, applies the sRGB transfer curve and quantises to 8 bits.
+    pub fn to_srgb8(&self, c: Vec3) -> [u8; 3] {
+        let c = self.map(c).map(linear_to_srgb);
+        let q = |v: f64| (v * 255.0).round() as u8;
+        [q(c.x()), q(c.y()), q(c.z())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_curve_endpoints() {
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-12);
+        // middle grey is about 188 in sRGB
+        let grey = ToneMapper::default().to_srgb8(vec3(0.5, 0.5, 0.5));
+        assert_eq!(grey, [188, 188, 188]);
+    }
+
+    #[test]
+    fn bright_values_saturate_instead_of_wrapping() {
+        for &operator in &[
+            ToneMap::Clamp,
+            ToneMap::Reinhard,
+            ToneMap::ExtendedReinhard { white: 4.0 },
+            ToneMap::Aces,
+        ] {
+            let tm = ToneMapper {
+                operator,
+                exposure: 0.0,
+            };
+            let bright = tm.to_srgb8(vec3(1000.0, 1000.0, 1000.0));
+            let dim = tm.to_srgb8(vec3(0.2, 0.2, 0.2));
+            assert!(bright[0] >= 250, "{:?}", operator);
+            assert!(dim[0] < bright[0], "{:?}", operator);
+        }
+    }
+
+    #[test]
+    fn extended_reinhard_maps_white_to_one() {
+        let tm = ToneMapper {
+            operator: ToneMap::ExtendedReinhard { white: 4.0 },
+            exposure: 0.0,
+        };
+        assert!((tm.map(vec3(4.0, 4.0, 4.0)).x() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let tm = ToneMapper {
+            operator: ToneMap::Clamp,
+            exposure: 1.0,
+        };
+        assert_eq!(tm.map(vec3(0.25, 0.25, 0.25)), vec3(0.5, 0.5, 0.5));
+    }
+}
@@ -62,6 +62,10 @@ impl Vec3 {
         self.e[0].powi(2) + self.e[1].powi(2) + self.e[2].powi(2)
     }
 
+    pub fn max_component(&self) -> f64 {
+        self.e[0].max(self.e[1]).max(self.e[2])
+    }
+
     pub fn unit_vector(&self) -> Vec3 {
         let l = self.length();
         *self / l
@@ -82,6 +86,10 @@ impl Vec3 {
         }
     }
 
+    pub fn random_unit_vector() -> Vec3 {
+        Vec3::random_in_unit_sphere().unit_vector()
+    }
+
     pub fn random_in_unit_disk() -> Vec3 {
         let mut rng = thread_rng();
 
@@ -101,6 +109,32 @@ pub fn vec3<T: Into<f64>>(x: T, y: T, z: T) -> Vec3 {
     Vec3::new([x.into(), y.into(), z.into()])
 }
 
+/// An orthonormal basis built around a single direction `w`.
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(n: Vec3) -> Onb {
+        let w = n.unit_vector();
+        let a = if w.x().abs() > 0.9 {
+            vec3(0, 1, 0)
+        } else {
+            vec3(1, 0, 0)
+        };
+        let v = w.cross(a).unit_vector();
+        let u = w.cross(v);
+        Onb { u, v, w }
+    }
+
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+}
+
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
     v - 2.0 * dot(v, n) * n
 }
@@ -245,3 +279,397 @@ impl Ray {
         self.time
     }
 }
+
+/// A row-major 4x4 matrix acting on column vectors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Mat4 { m }
+    }
+
+    pub fn translate(t: Vec3) -> Mat4 {
+        let mut r = Mat4::identity();
+        for i in 0..3 {
+            r.m[i][3] = t[i];
+        }
+        r
+    }
+
+    pub fn scale(s: Vec3) -> Mat4 {
+        let mut r = Mat4::identity();
+        for i in 0..3 {
+            r.m[i][i] = s[i];
+        }
+        r
+    }
+
+    /// Rotation by `degrees` around `axis`, counter-clockwise when looking
+    /// down the axis towards the origin.
+    pub fn rotate(axis: Vec3, degrees: f64) -> Mat4 {
+        let a = axis.unit_vector();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        let t = 1.0 - cos;
+        Mat4 {
+            m: [
+                [
+                    t * x * x + cos,
+                    t * x * y - sin * z,
+                    t * x * z + sin * y,
+                    0.0,
+                ],
+                [
+                    t * x * y + sin * z,
+                    t * y * y + cos,
+                    t * y * z - sin * x,
+                    0.0,
+                ],
+                [
+                    t * x * z - sin * y,
+                    t * y * z + sin * x,
+                    t * z * z + cos,
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut r = [[0.0; 4]; 4];
+        for (i, row) in r.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Mat4 { m: r }
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting; `None` if singular.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let d = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= d;
+                inv[col][j] /= d;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4 { m: inv })
+    }
+
+    /// The determinant of the upper-left 3x3 block.
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        let mut r = [0.0; 3];
+        for (i, v) in r.iter_mut().enumerate() {
+            *v = m[i][0] * p.x() + m[i][1] * p.y() + m[i][2] * p.z() + m[i][3];
+        }
+        Vec3::new(r)
+    }
+
+    pub fn transform_vector(&self, d: Vec3) -> Vec3 {
+        let m = &self.m;
+        let mut r = [0.0; 3];
+        for (i, v) in r.iter_mut().enumerate() {
+            *v = m[i][0] * d.x() + m[i][1] * d.y() + m[i][2] * d.z();
+        }
+        Vec3::new(r)
+    }
+}
+
+impl Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut r = [[0.0; 4]; 4];
+        for (i, row) in r.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Mat4 { m: r }
+    }
+}
+
+/// An affine transform together with its inverse.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub matrix: Mat4,
+    pub inverse: Mat4,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            matrix: Mat4::identity(),
+            inverse: Mat4::identity(),
+        }
+    }
+}
+
+impl Transform {
+    /// `None` if the matrix can't be inverted.
+    pub fn from_matrix(matrix: Mat4) -> Option<Transform> {
+        Some(Transform {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    pub fn translate(t: Vec3) -> Transform {
+        Transform {
+            matrix: Mat4::translate(t),
+            inverse: Mat4::translate(-t),
+        }
+    }
+
+    /// Panics on a zero scale factor.
+    pub fn scale(s: Vec3) -> Transform {
+        assert!(s.x() != 0.0 && s.y() != 0.0 && s.z() != 0.0, "zero scale");
+        Transform {
+            matrix: Mat4::scale(s),
+            inverse: Mat4::scale(s.map(|c| 1.0 / c)),
+        }
+    }
+
+    pub fn rotate(axis: Vec3, degrees: f64) -> Transform {
+        let matrix = Mat4::rotate(axis, degrees);
+        Transform {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    pub fn from_quat(q: Quat) -> Transform {
+        let matrix = q.to_mat4();
+        Transform {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    /// This transform followed by `next`.
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform {
+            matrix: next.matrix * self.matrix,
+            inverse: self.inverse * next.inverse,
+        }
+    }
+
+    pub fn inverted(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, d: Vec3) -> Vec3 {
+        self.matrix.transform_vector(d)
+    }
+
+    /// Normals transform by the inverse transpose, so they stay perpendicular
+    /// to surfaces under non-uniform scaling.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+}
+
+/// A unit quaternion, for rotations that need to be interpolated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quat {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Default for Quat {
+    fn default() -> Quat {
+        Quat {
+            w: 1.0,
+            v: vec3(0, 0, 0),
+        }
+    }
+}
+
+impl Quat {
+    /// The same rotation as `Mat4::rotate(axis, degrees)`.
+    pub fn from_axis_angle(axis: Vec3, degrees: f64) -> Quat {
+        let (sin, cos) = (degrees.to_radians() / 2.0).sin_cos();
+        Quat {
+            w: cos,
+            v: axis.unit_vector() * sin,
+        }
+    }
+
+    pub fn dot(&self, other: Quat) -> f64 {
+        self.w * other.w + dot(self.v, other.v)
+    }
+
+    fn normalized(&self) -> Quat {
+        let len = self.dot(*self).sqrt();
+        Quat {
+            w: self.w / len,
+            v: self.v / len,
+        }
+    }
+
+    /// The angle in radians of the rotation taking `self` to `other`.
+    pub fn angle_to(&self, other: Quat) -> f64 {
+        2.0 * self.dot(other).abs().min(1.0).acos()
+    }
+
+    /// Spherical interpolation along the shorter arc, turning at a constant
+    /// rate from `self` at `t = 0` to `other` at `t = 1`.
+    pub fn slerp(&self, other: Quat, t: f64) -> Quat {
+        let (other, cos) = if self.dot(other) < 0.0 {
+            (
+                Quat {
+                    w: -other.w,
+                    v: -other.v,
+                },
+                -self.dot(other),
+            )
+        } else {
+            (other, self.dot(other))
+        };
+        // nearly parallel rotations interpolate linearly to avoid dividing
+        // by a vanishing sine
+        let (a, b) = if cos > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - t) * theta).sin() / sin, (t * theta).sin() / sin)
+        };
+        Quat {
+            w: a * self.w + b * other.w,
+            v: a * self.v + b * other.v,
+        }
+        .normalized()
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        let (w, x, y, z) = (self.w, self.v.x(), self.v.y(), self.v.z());
+        Mat4 {
+            m: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn transforms_compose_in_order() {
+        let t = Transform::scale(vec3(2, 1, 1))
+            .then(&Transform::rotate(vec3(0, 0, 1), 90.0))
+            .then(&Transform::translate(vec3(0, 0, 5)));
+        assert_close(t.point(vec3(1, 0, 0)), vec3(0, 2, 5));
+        assert_close(t.vector(vec3(1, 0, 0)), vec3(0, 2, 0));
+        assert_close(t.inverted().point(vec3(0, 2, 5)), vec3(1, 0, 0));
+    }
+
+    #[test]
+    fn matrix_inverse() {
+        let m = Mat4::rotate(vec3(1, 1, 0), 30.0) * Mat4::translate(vec3(1, 2, 3));
+        let id = m * m.inverse().unwrap();
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((id.m[i][j] - expected).abs() < 1e-12);
+            }
+        }
+        assert!(Mat4::scale(vec3(1, 0, 1)).inverse().is_none());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let t = Transform::scale(vec3(4, 1, 1));
+        // the surface x + y = 1 has normal (1, 1, 0) and contains (1, -1, 0)
+        let n = t.normal(vec3(1, 1, 0));
+        assert!(dot(n, t.vector(vec3(1, -1, 0))).abs() < 1e-12);
+    }
+
+    #[test]
+    fn quaternions_match_matrices_and_slerp() {
+        let axis = vec3(1, 2, 3);
+        let q = Quat::from_axis_angle(axis, 70.0);
+        let p = vec3(0.5, -1.0, 2.0);
+        assert_close(
+            q.to_mat4().transform_point(p),
+            Mat4::rotate(axis, 70.0).transform_point(p),
+        );
+
+        // halfway through a quarter turn is an eighth turn, not a shrunken
+        // average of the two matrices
+        let start = Quat::default();
+        let end = Quat::from_axis_angle(vec3(0, 0, 1), 90.0);
+        let half = Transform::from_quat(start.slerp(end, 0.5));
+        let s = 0.5f64.sqrt();
+        assert_close(half.point(vec3(1, 0, 0)), vec3(s, s, 0.0));
+        assert!((start.angle_to(end) - std::f64::consts::FRAC_PI_2).abs() < 1e-12);
+    }
+}